@@ -1,27 +1,19 @@
 use anyhow::{bail, Context};
+use aoc::congruence;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{collections::HashMap, env, fmt::Write, fs};
 
+/// Note: Run this daily solution with DOT=<file> and/or GRAPHML=<file> env vars to export the node
+/// network to those files. Adding CYCLES=1 also highlights the loop each ghost ends up walking.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let (instructions, nodes) = input.split_once("\n\n").context("invalid input")?;
     let nodes = nodes.lines().map(parse_node).try_collect()?;
 
-    let ans_1 = count_steps("AAA", instructions, &nodes)?;
-
-    // This part 2 solution assumes:
-    // 1. That the amount of steps to reach the end from start_id is a multiple of the instructions
-    //    count for each start_id.
-    // 2. That those amounts of steps are also equal to the amount of steps that it takes to reach
-    //    the end again if we keep going.
-    //
-    // With these assumptions —which are true for the puzzle input— the answer is then the least
-    // common multiple of all these counts.
-    let ans_2 = nodes
-        .keys()
-        .filter(|id| id.ends_with('A'))
-        .map(|start_id| count_steps(start_id, instructions, &nodes))
-        .fold_ok(1, lcm)?;
+    let (ans_1, _) = count_steps("AAA", instructions, &nodes)?.context("end not reachable")?;
+    let ans_2 = count_ghost_steps(instructions, &nodes)?;
+
+    export_network(instructions, &nodes)?;
 
     println!("{ans_1} {ans_2}");
     Ok(())
@@ -29,39 +21,226 @@ fn main() -> aoc::Result<()> {
 
 type Graph<'a> = HashMap<&'a str, (&'a str, &'a str)>;
 
-fn count_steps(start: &str, instructions: &str, nodes: &Graph) -> aoc::Result<u64> {
-    let mut inst_iter = instructions.chars().cycle();
+/// Counts the steps needed to go from `start` to an end node (i.e. ending with 'Z'). Returns the
+/// number of steps and the end node reached, or None if no end node can be reached.
+fn count_steps<'a>(
+    start: &'a str,
+    instructions: &str,
+    nodes: &Graph<'a>,
+) -> aoc::Result<Option<(u64, &'a str)>> {
+    anyhow::ensure!(!instructions.is_empty(), "no instructions found");
+    // After going through all (node, instruction index) states we'd just be walking in circles.
+    let max_steps = nodes.len() * instructions.len();
     let mut curr = start;
-    let mut count = 0;
-    loop {
-        let (left, right) = nodes
-            .get(curr)
-            .with_context(|| format!("node '{curr}' not found"))?;
-        let inst = inst_iter.next().context("no instructions found")?;
-        match inst {
-            'L' => curr = left,
-            'R' => curr = right,
-            _ => bail!("unexpected instruction char '{inst}'"),
+    for (inst, count) in instructions.chars().cycle().take(max_steps).zip(1..) {
+        curr = next_node(curr, inst, nodes)?;
+        if curr.ends_with('Z') {
+            return Ok(Some((count, curr)));
         }
-        count += 1;
+    }
+    Ok(None)
+}
+
+fn next_node<'a>(curr: &str, inst: char, nodes: &Graph<'a>) -> aoc::Result<&'a str> {
+    let (left, right) = nodes
+        .get(curr)
+        .with_context(|| format!("node '{curr}' not found"))?;
+    match inst {
+        'L' => Ok(left),
+        'R' => Ok(right),
+        _ => bail!("unexpected instruction char '{inst}'"),
+    }
+}
+
+fn count_ghost_steps(instructions: &str, nodes: &Graph) -> aoc::Result<u128> {
+    let start_ids = nodes
+        .keys()
+        .copied()
+        .filter(|id| id.ends_with('A'))
+        .collect_vec();
+    if let Some(steps) = count_ghost_steps_lcm(&start_ids, instructions, nodes)? {
+        return Ok(steps);
+    }
+
+    let end_cycles: Vec<_> = start_ids
+        .iter()
+        .map(|start_id| find_ghost_cycle(start_id, instructions, nodes).map(|c| c.ends))
+        .try_collect()?;
+    congruence::first_common_hit(&end_cycles)
+        .context("ghosts never reach end nodes at the same time")
+}
+
+// This fast path checks the assumptions that hold for the puzzle input:
+// 1. That the amount of steps to reach the end from start_id is a multiple of the instructions
+//    count for each start_id.
+// 2. That those amounts of steps are also equal to the amount of steps that it takes to reach
+//    the same end again if we keep going.
+//
+// With these assumptions, the answer is then the least common multiple of all these counts.
+// Returns None if the assumptions don't hold.
+fn count_ghost_steps_lcm(
+    start_ids: &[&str],
+    instructions: &str,
+    nodes: &Graph,
+) -> aoc::Result<Option<u128>> {
+    let mut ans = 1;
+    for start_id in start_ids {
+        let Some((steps, end_id)) = count_steps(start_id, instructions, nodes)? else {
+            return Ok(None);
+        };
+        if steps % instructions.len() as u64 != 0 {
+            return Ok(None);
+        }
+        if count_steps(end_id, instructions, nodes)? != Some((steps, end_id)) {
+            return Ok(None);
+        }
+        ans = congruence::lcm(ans, steps as u128)?;
+    }
+    Ok(Some(ans))
+}
+
+/// The path of a ghost over the (node, instruction index) state space, which always ends up
+/// looping.
+struct GhostCycle<'a> {
+    /// The step counts at which the ghost is on an end node.
+    ends: congruence::Periodic,
+    /// The (node, instruction) connections the ghost walks through once inside the loop.
+    loop_edges: Vec<(&'a str, char)>,
+}
+
+fn find_ghost_cycle<'a>(
+    start: &'a str,
+    instructions: &str,
+    nodes: &Graph<'a>,
+) -> aoc::Result<GhostCycle<'a>> {
+    let inst_count = instructions.len();
+    let mut seen = HashMap::new();
+    let mut path = vec![];
+    let mut end_steps = vec![];
+    let mut curr = start;
+    for (inst, steps) in instructions.chars().cycle().zip(0..) {
+        let state = (curr, steps as usize % inst_count);
+        if let Some(&offset) = seen.get(&state) {
+            let length = steps - offset;
+            let loop_edges = path[offset as usize..].iter().copied().unique().collect();
+            return Ok(GhostCycle {
+                ends: congruence::Periodic {
+                    offset,
+                    length,
+                    hits: end_steps,
+                },
+                loop_edges,
+            });
+        }
+        seen.insert(state, steps);
+        path.push((curr, inst));
         if curr.ends_with('Z') {
-            return Ok(count);
+            end_steps.push(steps);
+        }
+        curr = next_node(curr, inst, nodes)?;
+    }
+    bail!("no instructions found")
+}
+
+fn export_network(instructions: &str, nodes: &Graph) -> aoc::Result<()> {
+    let dot_file = env::var("DOT").ok();
+    let graphml_file = env::var("GRAPHML").ok();
+    if dot_file.is_none() && graphml_file.is_none() {
+        return Ok(());
+    }
+
+    let mut ghost_loops = vec![];
+    if env::var("CYCLES").is_ok() {
+        for start_id in nodes.keys().filter(|id| id.ends_with('A')).sorted() {
+            let cycle = find_ghost_cycle(start_id, instructions, nodes)?;
+            ghost_loops.push((*start_id, cycle.loop_edges));
         }
     }
+
+    if let Some(file) = dot_file {
+        fs::write(&file, network_to_dot(nodes, &ghost_loops)?)
+            .with_context(|| format!("could not write DOT file '{file}'"))?;
+    }
+    if let Some(file) = graphml_file {
+        fs::write(&file, network_to_graphml(nodes, &ghost_loops)?)
+            .with_context(|| format!("could not write GraphML file '{file}'"))?;
+    }
+    Ok(())
 }
 
-fn gcd(a: u64, b: u64) -> u64 {
-    if b == 0 {
-        return a;
+type GhostLoop<'a> = (&'a str, Vec<(&'a str, char)>);
+
+const LOOP_COLORS: [&str; 8] = [
+    "red", "blue", "green3", "orange", "purple", "cyan3", "magenta", "gold3",
+];
+
+fn network_to_dot(nodes: &Graph, ghost_loops: &[GhostLoop]) -> aoc::Result<String> {
+    let mut dot = String::new();
+    writeln!(dot, "digraph network {{")?;
+    for &id in nodes.keys().sorted() {
+        let shape = match id {
+            _ if id.ends_with('A') => "box",
+            _ if id.ends_with('Z') => "doublecircle",
+            _ => "circle",
+        };
+        writeln!(dot, "  \"{id}\" [shape={shape}];")?;
+    }
+    for (&id, &(left, right)) in nodes.iter().sorted() {
+        for (target, inst) in [(left, 'L'), (right, 'R')] {
+            let loop_style = ghost_loops
+                .iter()
+                .zip(LOOP_COLORS.iter().cycle())
+                .find(|((_, loop_edges), _)| loop_edges.contains(&(id, inst)))
+                .map(|(_, color)| format!(", color={color}, penwidth=3"))
+                .unwrap_or_default();
+            writeln!(
+                dot,
+                "  \"{id}\" -> \"{target}\" [label=\"{inst}\"{loop_style}];"
+            )?;
+        }
     }
-    gcd(b, a % b)
+    writeln!(dot, "}}")?;
+    Ok(dot)
 }
 
-fn lcm(a: u64, b: u64) -> u64 {
-    if a == 0 || b == 0 {
-        return 0;
+fn network_to_graphml(nodes: &Graph, ghost_loops: &[GhostLoop]) -> aoc::Result<String> {
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        xml,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        xml,
+        r#"  <key id="loops" for="node" attr.name="ghost_loops" attr.type="string"/>"#
+    )?;
+    writeln!(
+        xml,
+        r#"  <key id="inst" for="edge" attr.name="instruction" attr.type="string"/>"#
+    )?;
+    writeln!(xml, r#"  <graph id="network" edgedefault="directed">"#)?;
+    for &id in nodes.keys().sorted() {
+        let loops = ghost_loops
+            .iter()
+            .filter(|(_, edges)| edges.iter().any(|&(from, _)| from == id))
+            .map(|(start_id, _)| start_id)
+            .join(",");
+        writeln!(
+            xml,
+            r#"    <node id="{id}"><data key="loops">{loops}</data></node>"#
+        )?;
+    }
+    for (&id, &(left, right)) in nodes.iter().sorted() {
+        for (target, inst) in [(left, 'L'), (right, 'R')] {
+            writeln!(
+                xml,
+                r#"    <edge source="{id}" target="{target}"><data key="inst">{inst}</data></edge>"#
+            )?;
+        }
     }
-    (a * b) / gcd(a, b)
+    writeln!(xml, "  </graph>")?;
+    writeln!(xml, "</graphml>")?;
+    Ok(xml)
 }
 
 fn parse_node(s: &str) -> aoc::Result<(&str, (&str, &str))> {
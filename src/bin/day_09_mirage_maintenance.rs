@@ -1,34 +1,73 @@
+use anyhow::Context;
 use itertools::Itertools;
 
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let sequences: Vec<_> = input.lines().map(aoc::parse_numbers).try_collect()?;
-    let ans_1: i64 = sequences.iter().map(|s| extrapolate(s)).sum();
-    let ans_2: i64 = sequences.iter().map(|s| extrapolate_back(s)).sum();
+    let sequences: Vec<Vec<i64>> = input.lines().map(aoc::parse_numbers).try_collect()?;
+    let polynomials: Vec<_> = sequences.iter().map(|s| Polynomial::fit(s)).try_collect()?;
+    let ans_1 = polynomials
+        .iter()
+        .zip(sequences.iter())
+        .map(|(p, s)| p.eval(s.len() as i64))
+        .try_fold(0, checked_sum)?;
+    let ans_2 = polynomials
+        .iter()
+        .map(|p| p.eval(-1))
+        .try_fold(0, checked_sum)?;
     println!("{ans_1} {ans_2}");
     Ok(())
 }
 
-fn extrapolate(sequence: &[i64]) -> i64 {
-    if sequence.iter().all(|&n| n == 0) {
-        return 0;
-    }
-    let diffs = differences(sequence);
-    extrapolate(&diffs) + sequence[sequence.len() - 1]
+fn checked_sum(sum: i64, value: aoc::Result<i64>) -> aoc::Result<i64> {
+    sum.checked_add(value?)
+        .context("overflow adding up extrapolated values")
 }
 
-fn extrapolate_back(sequence: &[i64]) -> i64 {
-    if sequence.iter().all(|&n| n == 0) {
-        return 0;
-    }
-    let diffs = differences(sequence);
-    sequence[0] - extrapolate_back(&diffs)
+/// The polynomial of minimum degree that passes through all the values of a sequence, with the
+/// value at index `x` being `p(x)`.
+///
+/// It's represented in the Newton forward differences form:
+/// p(x) = Δ⁰a₀ + Δ¹a₀·C(x, 1) + Δ²a₀·C(x, 2) + ...
+/// Where Δᵏa₀ is the first element of the k-th differences sequence, and C(x, k) is the binomial
+/// coefficient "x choose k", which works for negative numbers too.
+///
+/// See https://en.wikipedia.org/wiki/Newton_polynomial#Newton_forward_divided_difference_formula
+struct Polynomial {
+    forward_diffs: Vec<i128>,
 }
 
-fn differences(sequence: &[i64]) -> Vec<i64> {
-    sequence
-        .iter()
-        .tuple_windows()
-        .map(|(a, b)| b - a)
-        .collect()
+impl Polynomial {
+    fn fit(sequence: &[i64]) -> aoc::Result<Polynomial> {
+        // Compute all the differences sequences in place, leaving the first element of the k-th
+        // differences sequence at index k.
+        let mut forward_diffs = sequence.iter().map(|&n| n as i128).collect_vec();
+        for level in 1..forward_diffs.len() {
+            for i in (level..forward_diffs.len()).rev() {
+                forward_diffs[i] = forward_diffs[i]
+                    .checked_sub(forward_diffs[i - 1])
+                    .context("overflow calculating differences")?;
+            }
+        }
+        // Trailing zero differences don't contribute anything, so we can drop them.
+        while forward_diffs.last() == Some(&0) {
+            forward_diffs.pop();
+        }
+        Ok(Polynomial { forward_diffs })
+    }
+
+    fn eval(&self, x: i64) -> aoc::Result<i64> {
+        let overflow = || format!("overflow evaluating polynomial at {x}");
+        let x = x as i128;
+        let mut result: i128 = 0;
+        let mut binomial: i128 = 1;
+        for (k, diff) in (0..).zip(self.forward_diffs.iter()) {
+            if k > 0 {
+                // C(x, k) = C(x, k - 1) * (x - k + 1) / k, and the division is always exact.
+                binomial = binomial.checked_mul(x - k + 1).with_context(overflow)? / k;
+            }
+            let term = binomial.checked_mul(*diff).with_context(overflow)?;
+            result = result.checked_add(term).with_context(overflow)?;
+        }
+        result.try_into().with_context(overflow)
+    }
 }
@@ -1,12 +1,29 @@
 use anyhow::Context;
 use itertools::Itertools;
+use std::{env, fmt::Write, fs};
 
+/// Note: Run this daily solution with SCANLINE=1 env var to count the enclosed tiles by scanning
+/// each row instead of using the loop area. Both methods should give the same answer.
+///
+/// Run with DEBUG=1 env var to draw the pipe maze in the terminal, or with HTML=<file> to draw it
+/// into an HTML file.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let (grid, ..) = aoc::parse_char_grid(&input)?;
 
-    let half_pipe_length = measure_pipe_loop(&grid)? / 2;
-    let enclosed_count = count_enclosed_tiles(&grid);
+    let pipe_loop = find_pipe_loop(&grid)?;
+    let half_pipe_length = pipe_loop.tiles.len() / 2;
+    let enclosed_count = if env::var("SCANLINE").is_ok() {
+        count_enclosed_tiles_scanline(&clean_grid(&grid, &pipe_loop))
+    } else {
+        count_enclosed_tiles(&pipe_loop)
+    };
+
+    debug_print_maze(&grid, &pipe_loop);
+    if let Ok(file) = env::var("HTML") {
+        fs::write(&file, render_maze_html(&grid, &pipe_loop)?)
+            .with_context(|| format!("could not write HTML file '{file}'"))?;
+    }
 
     println!("{half_pipe_length} {enclosed_count}");
     Ok(())
@@ -16,7 +33,7 @@ fn main() -> aoc::Result<()> {
 type Grid = Vec<Vec<char>>;
 type Point = (usize, usize);
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Dir {
     Up,
     Right,
@@ -25,24 +42,39 @@ enum Dir {
 }
 use Dir::*;
 
-fn measure_pipe_loop(grid: &Grid) -> aoc::Result<u32> {
+struct PipeLoop {
+    /// The positions of all the loop tiles, in walking order, ending with the start position.
+    tiles: Vec<Point>,
+    /// The pipe shape hidden under the 'S' tile, inferred from how the loop connects to it.
+    start_pipe: char,
+}
+
+fn find_pipe_loop(grid: &Grid) -> aoc::Result<PipeLoop> {
     let start_pos = find_start_position(grid).context("start position not found")?;
-    let start_dir = [Up, Right, Down, Left]
+    // There may be junk pipes connecting to the start position, so we try walking in every
+    // direction until we get back to the start.
+    [Up, Right, Down, Left]
         .into_iter()
-        .find(|d| try_move(start_pos, *d, grid).is_some())
-        .context("no valid direction from start position found")?;
+        .find_map(|start_dir| walk_pipe_loop(start_pos, start_dir, grid))
+        .context("no pipe loop going through the start position found")
+}
+
+fn walk_pipe_loop(start_pos: Point, start_dir: Dir, grid: &Grid) -> Option<PipeLoop> {
+    let max_length = grid.len() * grid[0].len();
     let mut pos = start_pos;
     let mut dir = start_dir;
-    let mut count = 0;
-    loop {
-        (pos, dir) = try_move(pos, dir, grid)
-            .with_context(|| format!("invalid turn, going {dir:?} from {pos:?}"))?;
-        count += 1;
+    let mut tiles = vec![];
+    while tiles.len() < max_length {
+        (pos, dir) = try_move(pos, dir, grid)?;
+        tiles.push(pos);
         if pos == start_pos {
-            break;
+            // We arrived to the start going in `dir`, so the start pipe connects to the opposite
+            // direction of that, and to the direction we started walking in.
+            let start_pipe = pipe_connecting(start_dir, opposite(dir))?;
+            return Some(PipeLoop { tiles, start_pipe });
         }
     }
-    Ok(count)
+    None
 }
 
 fn try_move(pos: Point, dir: Dir, grid: &Grid) -> Option<(Point, Dir)> {
@@ -67,6 +99,28 @@ fn try_move(pos: Point, dir: Dir, grid: &Grid) -> Option<(Point, Dir)> {
     Some(((x, y), new_dir))
 }
 
+fn pipe_connecting(a: Dir, b: Dir) -> Option<char> {
+    let pipe = match (a, b) {
+        (Up, Down) | (Down, Up) => '|',
+        (Left, Right) | (Right, Left) => '-',
+        (Up, Right) | (Right, Up) => 'L',
+        (Up, Left) | (Left, Up) => 'J',
+        (Down, Left) | (Left, Down) => '7',
+        (Down, Right) | (Right, Down) => 'F',
+        _ => return None,
+    };
+    Some(pipe)
+}
+
+fn opposite(dir: Dir) -> Dir {
+    match dir {
+        Up => Down,
+        Down => Up,
+        Left => Right,
+        Right => Left,
+    }
+}
+
 fn find_start_position(grid: &Grid) -> Option<Point> {
     for (row, y) in grid.iter().zip(0..) {
         for (ch, x) in row.iter().zip(0..) {
@@ -79,114 +133,160 @@ fn find_start_position(grid: &Grid) -> Option<Point> {
 }
 
 // Part 2 stuff
-type FloodFillGrid = Vec<Vec<FloodFillTile>>;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum FloodFillTile {
-    Empty,
-    Wall,
-    Filled,
-}
-use FloodFillTile::*;
-
-// This solution to part 2 assumes that there are no other loops of pipe on the grid besides giant
-// giant one. And that there's no 'J' or 'S' on the top left corner of the grid, which would mess up
-// the flood-filling starting there. If these assumptions would not hold, we'd need to clean up the
-// grid of all the junk pipes outside the giant loop before doing the flood-fill.
-fn count_enclosed_tiles(grid: &Grid) -> u32 {
-    let mut expanded_grid = expand_grid(grid);
-    flood_fill(&mut expanded_grid);
-
-    let mut enclosed_count = 0;
-    for (y, row) in grid.iter().enumerate() {
-        for (x, _ch) in row.iter().enumerate() {
-            let is_enclosed = (0..3)
-                .cartesian_product(0..3)
-                .all(|(dx, dy)| expanded_grid[y * 3 + dy][x * 3 + dx] != Filled);
-            enclosed_count += is_enclosed as u32;
-        }
-    }
-    enclosed_count
+/// Counts the tiles enclosed by the pipe loop by treating the centers of the loop tiles as the
+/// vertices of a polygon, and counting the lattice points inside it using its area and Pick's
+/// theorem.
+fn count_enclosed_tiles(pipe_loop: &PipeLoop) -> usize {
+    let vertices = pipe_loop
+        .tiles
+        .iter()
+        .map(|&(x, y)| (x as i64, y as i64))
+        .collect_vec();
+    aoc::polygon::interior_points(&vertices) as usize
 }
 
-fn expand_grid(grid: &Grid) -> FloodFillGrid {
-    let height = grid.len();
-    let width = grid[0].len();
-    let mut expanded_grid = vec![vec![Empty; width * 3]; height * 3];
+/// Counts the tiles enclosed by the pipe loop by scanning each row and keeping track of how many
+/// times we cross the loop. Expects a grid with no junk pipes, as returned by `clean_grid`.
+fn count_enclosed_tiles_scanline(grid: &Grid) -> usize {
+    find_enclosed_tiles(grid).len()
+}
+
+fn find_enclosed_tiles(grid: &Grid) -> Vec<Point> {
+    let mut enclosed_tiles = vec![];
     for (y, row) in grid.iter().enumerate() {
+        let mut inside = false;
         for (x, ch) in row.iter().enumerate() {
-            let expanded_tile = expand_tile(*ch);
-            for (tx, ty) in (0..3).cartesian_product(0..3) {
-                expanded_grid[y * 3 + ty][x * 3 + tx] = expanded_tile[ty][tx];
+            match ch {
+                // Only count pipes that connect upwards, so horizontal runs like `F--J` count as a
+                // single crossing, while `F--7` doesn't count as crossing at all.
+                '|' | 'L' | 'J' => inside = !inside,
+                '.' if inside => enclosed_tiles.push((x, y)),
+                _ => {}
             }
         }
     }
-    expanded_grid
-}
-
-fn expand_tile(ch: char) -> [[FloodFillTile; 3]; 3] {
-    #[allow(non_snake_case)]
-    let W = Wall;
-    let e = Empty;
-    match ch {
-        '|' => [
-            [e, W, e], //
-            [e, W, e],
-            [e, W, e],
-        ],
-        '-' => [
-            [e, e, e], //
-            [W, W, W],
-            [e, e, e],
-        ],
-        'L' => [
-            [e, W, e], //
-            [e, W, W],
-            [e, e, e],
-        ],
-        'J' => [
-            [e, W, e], //
-            [W, W, e],
-            [e, e, e],
-        ],
-        '7' => [
-            [e, e, e], //
-            [W, W, e],
-            [e, W, e],
-        ],
-        'F' => [
-            [e, e, e], //
-            [e, W, W],
-            [e, W, e],
-        ],
-        'S' => [
-            [e, W, e], // Leave some gaps in the corner so the flood-fill can get in.
-            [W, W, W],
-            [e, W, e],
-        ],
-        _ => [
-            [e, e, e], //
-            [e, e, e],
-            [e, e, e],
-        ],
+    enclosed_tiles
+}
+
+/// Returns a copy of the grid with only the pipes on the loop, and the 'S' tile replaced with its
+/// actual pipe shape.
+fn clean_grid(grid: &Grid, pipe_loop: &PipeLoop) -> Grid {
+    let mut clean_grid = vec![vec!['.'; grid[0].len()]; grid.len()];
+    for &(x, y) in pipe_loop.tiles.iter() {
+        clean_grid[y][x] = match grid[y][x] {
+            'S' => pipe_loop.start_pipe,
+            ch => ch,
+        };
+    }
+    clean_grid
+}
+
+// Rendering stuff
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TileKind {
+    Ground,
+    Junk,
+    Loop,
+    Start,
+    Enclosed,
+}
+
+/// Classifies each tile of the grid and converts pipes into box-drawing characters, which are much
+/// easier to follow than the ASCII ones.
+fn render_tiles(grid: &Grid, pipe_loop: &PipeLoop) -> Vec<Vec<(char, TileKind)>> {
+    let clean_grid = clean_grid(grid, pipe_loop);
+    let mut tiles = grid
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&ch| match box_drawing_char(ch) {
+                    Some(box_ch) => (box_ch, TileKind::Junk),
+                    None => (' ', TileKind::Ground),
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    for &(x, y) in pipe_loop.tiles.iter() {
+        let kind = if grid[y][x] == 'S' {
+            TileKind::Start
+        } else {
+            TileKind::Loop
+        };
+        let box_ch = box_drawing_char(clean_grid[y][x]).unwrap_or('?');
+        tiles[y][x] = (box_ch, kind);
+    }
+    for (x, y) in find_enclosed_tiles(&clean_grid) {
+        tiles[y][x] = ('•', TileKind::Enclosed);
     }
+    tiles
+}
+
+fn box_drawing_char(ch: char) -> Option<char> {
+    let box_ch = match ch {
+        '|' => '│',
+        '-' => '─',
+        'L' => '└',
+        'J' => '┘',
+        '7' => '┐',
+        'F' => '┌',
+        _ => return None,
+    };
+    Some(box_ch)
 }
 
-fn flood_fill(expanded_grid: &mut FloodFillGrid) {
-    let mut unvisited = vec![(0, 0)];
-    while let Some((x, y)) = unvisited.pop() {
-        expanded_grid[y][x] = Filled;
-        let neighbors = [
-            (x, y.wrapping_sub(1)),
-            (x, y + 1),
-            (x.wrapping_sub(1), y),
-            (x + 1, y),
-        ];
-        for (nx, ny) in neighbors {
-            let neighbor_tile = expanded_grid.get(ny).and_then(|row| row.get(nx));
-            if let Some(&Empty) = neighbor_tile {
-                unvisited.push((nx, ny))
+fn debug_print_maze(grid: &Grid, pipe_loop: &PipeLoop) {
+    if env::var("DEBUG").is_err() {
+        return;
+    }
+    for row in render_tiles(grid, pipe_loop) {
+        let mut line = String::new();
+        for (ch, kind) in row {
+            match kind {
+                TileKind::Ground => line.push(ch),
+                TileKind::Junk => line.push_str(&format!("\x1b[2m{ch}\x1b[0m")),
+                TileKind::Loop => line.push_str(&format!("\x1b[34;1m{ch}\x1b[0m")),
+                TileKind::Start => line.push_str(&format!("\x1b[31;1m{ch}\x1b[0m")),
+                TileKind::Enclosed => line.push_str(&format!("\x1b[32;1m{ch}\x1b[0m")),
             }
         }
+        println!("{line}");
+    }
+    println!();
+}
+
+fn render_maze_html(grid: &Grid, pipe_loop: &PipeLoop) -> aoc::Result<String> {
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(
+        html,
+        "<html><head><meta charset=\"utf-8\"><title>Pipe Maze</title><style>"
+    )?;
+    writeln!(html, "body {{ background: #111; color: #eee; }}")?;
+    writeln!(html, "pre {{ line-height: 1; font-size: 12px; }}")?;
+    writeln!(html, ".junk {{ color: #555; }}")?;
+    writeln!(html, ".loop {{ color: #4af; font-weight: bold; }}")?;
+    writeln!(html, ".start {{ color: #f44; font-weight: bold; }}")?;
+    writeln!(html, ".enclosed {{ color: #4f4; }}")?;
+    writeln!(html, "</style></head><body><pre>")?;
+    for row in render_tiles(grid, pipe_loop) {
+        // Group consecutive tiles of the same kind to keep the number of spans down.
+        for (kind, tiles) in &row.into_iter().chunk_by(|&(_, kind)| kind) {
+            let text: String = tiles.map(|(ch, _)| ch).collect();
+            let class = match kind {
+                TileKind::Ground => {
+                    write!(html, "{text}")?;
+                    continue;
+                }
+                TileKind::Junk => "junk",
+                TileKind::Loop => "loop",
+                TileKind::Start => "start",
+                TileKind::Enclosed => "enclosed",
+            };
+            write!(html, "<span class=\"{class}\">{text}</span>")?;
+        }
+        writeln!(html)?;
     }
+    writeln!(html, "</pre></body></html>")?;
+    Ok(html)
 }
@@ -1,49 +1,73 @@
-use itertools::{iproduct, Itertools};
+use anyhow::Context;
 
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let image = aoc::parse_grid(&input, |ch| Ok(ch == '#'))?;
+    let galaxies = parse_galaxies(&input);
 
-    let ans_1 = galaxy_distances_sum(&image, 2);
-    let ans_2 = galaxy_distances_sum(&image, 1_000_000);
+    let ans_1 = galaxy_distances_sum(&galaxies, (2, 2))?;
+    let ans_2 = galaxy_distances_sum(&galaxies, (1_000_000, 1_000_000))?;
     println!("{ans_1} {ans_2}");
     Ok(())
 }
 
-type Image = (Vec<Vec<bool>>, usize, usize);
+type Point = (u64, u64);
 
-fn galaxy_distances_sum(image: &Image, expansion_factor: usize) -> usize {
-    galaxy_positions_after_expansion(image, expansion_factor)
-        .into_iter()
-        .tuple_combinations()
-        .map(|((x1, y1), (x2, y2))| x1.abs_diff(x2) + y1.abs_diff(y2))
-        .sum()
+/// Calculates the sum of the Manhattan distances between all pairs of galaxies, after expanding the
+/// empty columns and rows by the given (horizontal, vertical) expansion factors.
+///
+/// Manhattan distances can be calculated independently on each axis, so this works on sorted x and
+/// y coordinates separately, which takes O(n log n) instead of O(n²) for checking all pairs.
+fn galaxy_distances_sum(galaxies: &[Point], (x_factor, y_factor): (u64, u64)) -> aoc::Result<u128> {
+    let xs = galaxies.iter().map(|&(x, _)| x).collect();
+    let ys = galaxies.iter().map(|&(_, y)| y).collect();
+    let x_distances = axis_distances_sum(xs, x_factor)?;
+    let y_distances = axis_distances_sum(ys, y_factor)?;
+    x_distances
+        .checked_add(y_distances)
+        .context("distances sum overflow")
 }
 
-fn galaxy_positions_after_expansion(image: &Image, expansion_factor: usize) -> Vec<(usize, usize)> {
-    let (ref pixels, width, height) = *image;
+fn axis_distances_sum(mut coords: Vec<u64>, expansion_factor: u64) -> aoc::Result<u128> {
+    coords.sort_unstable();
+    let overflow = || "distances sum overflow";
 
-    let mut galaxies: Vec<_> = iproduct!(0..width, 0..height)
-        .filter(|&(x, y)| pixels[y][x])
-        .collect();
-
-    for row in (0..height).rev() {
-        let row_is_empty = !pixels[row].iter().contains(&true);
-        if row_is_empty {
-            for (_, y) in galaxies.iter_mut().filter(|(_, y)| *y > row) {
-                *y += expansion_factor - 1;
-            }
+    let mut sum: u128 = 0;
+    // Expanded coordinate of the current galaxy, relative to the first one.
+    let mut expanded: u128 = 0;
+    // Sum of the expanded coordinates of all previous galaxies.
+    let mut prefix_sum: u128 = 0;
+    let mut prev_coord = coords.first().copied().unwrap_or(0);
+    for (i, &coord) in (0..).zip(coords.iter()) {
+        if coord != prev_coord {
+            // All the lines between two consecutive galaxies are empty, so they get expanded.
+            let empty_lines = (coord - prev_coord - 1) as u128;
+            let expanded_gap = empty_lines
+                .checked_mul(expansion_factor as u128)
+                .and_then(|gap| gap.checked_add(1))
+                .with_context(overflow)?;
+            expanded = expanded.checked_add(expanded_gap).with_context(overflow)?;
+            prev_coord = coord;
         }
+        // The distances from this galaxy to the previous i ones add up to i times its coordinate
+        // minus the sum of the coordinates of those previous ones.
+        let distances = expanded
+            .checked_mul(i)
+            .and_then(|d| d.checked_sub(prefix_sum))
+            .with_context(overflow)?;
+        sum = sum.checked_add(distances).with_context(overflow)?;
+        prefix_sum = prefix_sum.checked_add(expanded).with_context(overflow)?;
     }
+    Ok(sum)
+}
 
-    for col in (0..width).rev() {
-        let col_is_empty = !pixels.iter().map(|row| row[col]).contains(&true);
-        if col_is_empty {
-            for (x, _) in galaxies.iter_mut().filter(|(x, _)| *x > col) {
-                *x += expansion_factor - 1;
+fn parse_galaxies(input: &str) -> Vec<Point> {
+    let mut galaxies = vec![];
+    for (line, y) in input.lines().zip(0..) {
+        for (ch, x) in line.chars().zip(0..) {
+            if ch == '#' {
+                galaxies.push((x, y));
             }
         }
     }
-
     galaxies
 }
@@ -1,17 +1,49 @@
 use anyhow::Context;
 use itertools::Itertools;
+use rand::Rng;
 use rayon::prelude::*;
 use regex::bytes::Regex;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+};
 
+/// Note: Run this daily solution with CROSS_CHECK=1 env var to also count arrangements using the
+/// old regex-based implementation, and check that both implementations agree on every record.
+///
+/// Run with ARRANGEMENTS="<record>" env var (e.g. ARRANGEMENTS="?###???????? 3,2,1") to print all
+/// possible arrangements of that record instead of solving the puzzle. Adding SAMPLES=<n> prints n
+/// arrangements picked at random instead.
+///
+/// Run with UNFOLD_FACTOR=<n> and/or UNFOLD_JOINER=<springs> env vars to change how records get
+/// unfolded for part 2. The defaults are 5 copies joined by a single '?'. Counts are u128s, which is
+/// enough for up to 11 copies of the real input. Beyond that the part 2 sum, and eventually the
+/// counts of single records, overflow, and this returns an error.
 fn main() -> aoc::Result<()> {
+    if let Ok(record) = env::var("ARRANGEMENTS") {
+        return print_arrangements(&record);
+    }
+
     let input = aoc::read_stdin()?;
+    let cross_check = env::var("CROSS_CHECK").is_ok();
+    let unfold_factor = match env::var("UNFOLD_FACTOR") {
+        Ok(n) => n.parse().context("UNFOLD_FACTOR must be a number")?,
+        Err(_) => 5,
+    };
+    let unfold_joiner = match env::var("UNFOLD_JOINER") {
+        Ok(joiner) => parse_springs(&joiner)?,
+        Err(_) => b"?".to_vec(),
+    };
 
     let records: Vec<_> = input.lines().map(parse_record).try_collect()?;
-    let ans_1 = possible_arrangements_sum(&records);
+    let ans_1 = possible_arrangements_sum(&records, cross_check)?;
 
-    let unfolded_records: Vec<_> = records.into_iter().map(unfold_record).collect();
-    let ans_2 = possible_arrangements_sum(&unfolded_records);
+    let unfolded_records: Vec<_> = records
+        .into_iter()
+        .map(|r| unfold_record(r, unfold_factor, &unfold_joiner))
+        .collect();
+    let ans_2 = possible_arrangements_sum(&unfolded_records, cross_check)?;
 
     println!("{ans_1} {ans_2}");
     Ok(())
@@ -19,30 +51,256 @@ fn main() -> aoc::Result<()> {
 
 type Record = (Vec<u8>, Vec<u64>);
 
-fn possible_arrangements_sum(records: &[Record]) -> u64 {
+fn possible_arrangements_sum(records: &[Record], cross_check: bool) -> aoc::Result<u128> {
     records
         .par_iter()
-        .map(|(row, groups)| {
-            let groups_re = generate_groups_regex(groups);
-            count_possible_arrangements(row, &groups_re, &mut HashMap::new())
+        .map_init(ArrangementsCounter::default, |counter, (row, groups)| {
+            let count = counter.count(row, groups)?;
+            if cross_check {
+                let groups_re = generate_groups_regex(groups);
+                let regex_count =
+                    count_possible_arrangements_regex(row, &groups_re, &mut HashMap::new())?;
+                anyhow::ensure!(
+                    count == regex_count,
+                    "arrangement counts differ for record '{}' {groups:?}: {count} != {regex_count}",
+                    String::from_utf8_lossy(row),
+                );
+            }
+            Ok(count)
+        })
+        .try_reduce(|| 0, |a, b| a.checked_add(b).context(COUNT_OVERFLOW))
+}
+
+const COUNT_OVERFLOW: &str = "arrangements count overflow";
+
+/// Counts the possible arrangements of rows of springs, reusing the same state machine and counts
+/// buffers for every row so that counting doesn't need to allocate.
+#[derive(Default)]
+struct ArrangementsCounter {
+    machine: GroupsMachine,
+    counts: Vec<u128>,
+    next_counts: Vec<u128>,
+}
+
+impl ArrangementsCounter {
+    /// Counts the possible arrangements of a row of springs by running all of them at the same
+    /// time on the groups state machine, keeping track of how many arrangements are on each state.
+    fn count(&mut self, row: &[u8], groups: &[u64]) -> aoc::Result<u128> {
+        let ArrangementsCounter {
+            machine,
+            counts,
+            next_counts,
+        } = self;
+        machine.rebuild(groups);
+        counts.clear();
+        counts.resize(machine.states_count(), 0);
+        next_counts.clear();
+        next_counts.resize(machine.states_count(), 0);
+        counts[0] = 1;
+        for &spring in row {
+            next_counts.fill(0);
+            for (state, &count) in counts.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                for next_state in machine.next_states(state, spring).into_iter().flatten() {
+                    next_counts[next_state] = count
+                        .checked_add(next_counts[next_state])
+                        .context(COUNT_OVERFLOW)?;
+                }
+            }
+            std::mem::swap(counts, next_counts);
+        }
+        (0..machine.states_count())
+            .filter(|&state| machine.is_accepting(state))
+            .try_fold(0, |total: u128, state| {
+                total.checked_add(counts[state]).context(COUNT_OVERFLOW)
+            })
+    }
+}
+
+/// A state machine that matches rows of springs with some given group numbers.
+///
+/// States are (group index, run length) pairs: how many groups of damaged springs we've already
+/// completed, and how many damaged springs we've seen on the current group. These are flattened
+/// into a single index, with state 0 being the initial one.
+#[derive(Default)]
+struct GroupsMachine {
+    /// For each state, the next state when seeing an operational or a damaged spring, if any.
+    transitions: Vec<[Option<usize>; 2]>,
+    accepting: Vec<bool>,
+}
+
+impl GroupsMachine {
+    fn new(groups: &[u64]) -> GroupsMachine {
+        let mut machine = GroupsMachine::default();
+        machine.rebuild(groups);
+        machine
+    }
+
+    /// Replaces the machine with one for the given group numbers, reusing its tables.
+    fn rebuild(&mut self, groups: &[u64]) {
+        let GroupsMachine {
+            transitions,
+            accepting,
+        } = self;
+        transitions.clear();
+        accepting.clear();
+        for (g, &group_len) in groups.iter().enumerate() {
+            let is_last_group = g == groups.len() - 1;
+            let group_start = transitions.len();
+            let next_group_start = group_start + group_len as usize + 1;
+            for run_len in 0..=group_len {
+                let state = transitions.len();
+                let on_operational = match run_len {
+                    // Operational spring: fine if not in a group, or if just finished one.
+                    0 => Some(state),
+                    _ if run_len == group_len => Some(next_group_start),
+                    _ => None,
+                };
+                // Damaged spring: fine if the current group is not complete yet.
+                let on_damaged = (run_len < group_len).then_some(state + 1);
+                transitions.push([on_operational, on_damaged]);
+                accepting.push(is_last_group && run_len == group_len);
+            }
+        }
+        // After all groups are complete, only operational springs are allowed.
+        let final_state = transitions.len();
+        transitions.push([Some(final_state), None]);
+        accepting.push(true);
+    }
+
+    fn states_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /// Returns the possible next states for the given spring, which may be a '?', in which case both
+    /// the operational and damaged next states are considered.
+    fn next_states(&self, state: usize, spring: u8) -> [Option<usize>; 2] {
+        let [on_operational, on_damaged] = self.transitions[state];
+        match spring {
+            b'.' => [on_operational, None],
+            b'#' => [None, on_damaged],
+            _ => [on_operational, on_damaged],
+        }
+    }
+
+    fn is_accepting(&self, state: usize) -> bool {
+        self.accepting[state]
+    }
+}
+
+/// All the possible arrangements of a row of springs, which can be enumerated lazily or sampled at
+/// random without having to generate all of them.
+struct Arrangements<'a> {
+    row: &'a [u8],
+    machine: GroupsMachine,
+    /// How many arrangements can be completed from each (row position, machine state).
+    completions: Vec<Vec<u128>>,
+}
+
+impl<'a> Arrangements<'a> {
+    fn new(row: &'a [u8], groups: &[u64]) -> aoc::Result<Arrangements<'a>> {
+        let machine = GroupsMachine::new(groups);
+        let mut completions = vec![vec![0; machine.states_count()]; row.len() + 1];
+        completions[row.len()] = (0..machine.states_count())
+            .map(|state| machine.is_accepting(state) as u128)
+            .collect();
+        for (pos, &spring) in row.iter().enumerate().rev() {
+            for state in 0..machine.states_count() {
+                completions[pos][state] = machine
+                    .next_states(state, spring)
+                    .into_iter()
+                    .flatten()
+                    .try_fold(0, |total: u128, next_state| {
+                        total
+                            .checked_add(completions[pos + 1][next_state])
+                            .context(COUNT_OVERFLOW)
+                    })?;
+            }
+        }
+        Ok(Arrangements {
+            row,
+            machine,
+            completions,
         })
-        .sum()
+    }
+
+    fn count(&self) -> u128 {
+        self.completions[0][0]
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        (0..self.count()).filter_map(|index| self.nth(index))
+    }
+
+    /// Returns the arrangement at the given index, with arrangements being ordered as if '.' came
+    /// before '#'. Because we know how many arrangements can follow each choice of spring, this
+    /// doesn't need to go through any of the previous arrangements.
+    fn nth(&self, mut index: u128) -> Option<Vec<u8>> {
+        let mut arrangement = Vec::with_capacity(self.row.len());
+        let mut state = 0;
+        for (pos, &spring) in self.row.iter().enumerate() {
+            let [on_operational, on_damaged] = self.machine.next_states(state, spring);
+            let choices = [(b'.', on_operational), (b'#', on_damaged)];
+            let (chosen_spring, next_state) = choices.into_iter().find_map(|(ch, next)| {
+                let next = next?;
+                let next_count = self.completions[pos + 1][next];
+                if index < next_count {
+                    Some((ch, next))
+                } else {
+                    index -= next_count;
+                    None
+                }
+            })?;
+            arrangement.push(chosen_spring);
+            state = next_state;
+        }
+        Some(arrangement)
+    }
+
+    /// Picks an arrangement uniformly at random.
+    fn sample(&self, rng: &mut impl Rng) -> Option<Vec<u8>> {
+        if self.count() == 0 {
+            return None;
+        }
+        self.nth(rng.gen_range(0..self.count()))
+    }
+}
+
+fn print_arrangements(record: &str) -> aoc::Result<()> {
+    let (row, groups) = parse_record(record)?;
+    let arrangements = Arrangements::new(&row, &groups)?;
+    let arrangements: Box<dyn Iterator<Item = Vec<u8>>> = match env::var("SAMPLES") {
+        Ok(n) => {
+            let mut rng = rand::thread_rng();
+            let n = n.parse().context("SAMPLES must be a number")?;
+            Box::new((0..n).filter_map(move |_| arrangements.sample(&mut rng)))
+        }
+        Err(_) => Box::new(arrangements.iter()),
+    };
+    let mut stdout = io::stdout().lock();
+    for arrangement in arrangements {
+        stdout.write_all(&arrangement)?;
+        stdout.write_all(b"\n")?;
+    }
+    Ok(())
 }
 
-fn count_possible_arrangements(
+fn count_possible_arrangements_regex(
     row: &[u8],
     groups_re: &Regex,
-    cache: &mut HashMap<(usize, usize), u64>,
-) -> u64 {
+    cache: &mut HashMap<(usize, usize), u128>,
+) -> aoc::Result<u128> {
     let Some(unknown_index) = row.iter().position(|&b| b == b'?') else {
-        return 1;
+        return Ok(1);
     };
-    let mut total_count = 0;
+    let mut total_count: u128 = 0;
     let mut new_row = row.to_vec();
 
     new_row[unknown_index] = b'#';
     if groups_re.is_match(&new_row) {
-        total_count += count_possible_arrangements(&new_row, groups_re, cache);
+        total_count = count_possible_arrangements_regex(&new_row, groups_re, cache)?;
     }
 
     new_row[unknown_index] = b'.';
@@ -58,16 +316,17 @@ fn count_possible_arrangements(
             .filter(|s| !s.is_empty())
             .count();
         let cache_key = (unknown_index, fixed_groups_count);
-        total_count += if let Some(count) = cache.get(&cache_key) {
+        let count = if let Some(count) = cache.get(&cache_key) {
             *count
         } else {
-            let count = count_possible_arrangements(&new_row, groups_re, cache);
+            let count = count_possible_arrangements_regex(&new_row, groups_re, cache)?;
             cache.insert(cache_key, count);
             count
-        }
+        };
+        total_count = total_count.checked_add(count).context(COUNT_OVERFLOW)?;
     };
 
-    total_count
+    Ok(total_count)
 }
 
 /// Creates a Regex that can check if a row of springs matches the given group numbers.
@@ -84,13 +343,20 @@ fn generate_groups_regex(groups: &[u64]) -> Regex {
 fn parse_record(line: &str) -> aoc::Result<Record> {
     let (springs_row, group_numbers) = line.split_once(' ').context("invalid input")?;
     Ok((
-        springs_row.as_bytes().to_vec(),
+        parse_springs(springs_row)?,
         aoc::parse_numbers(group_numbers)?,
     ))
 }
 
-fn unfold_record((springs_row, group_numbers): Record) -> Record {
-    let unfolded_row = [springs_row.as_slice()].repeat(5).join(&b'?');
-    let unfolded_numbers = group_numbers.repeat(5);
+fn parse_springs(s: &str) -> aoc::Result<Vec<u8>> {
+    if let Some(ch) = s.chars().find(|ch| !matches!(ch, '.' | '#' | '?')) {
+        anyhow::bail!("invalid spring '{ch}'");
+    }
+    Ok(s.as_bytes().to_vec())
+}
+
+fn unfold_record((springs_row, group_numbers): Record, factor: usize, joiner: &[u8]) -> Record {
+    let unfolded_row = [springs_row.as_slice()].repeat(factor).join(joiner);
+    let unfolded_numbers = group_numbers.repeat(factor);
     (unfolded_row, unfolded_numbers)
 }
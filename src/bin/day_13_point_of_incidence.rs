@@ -1,47 +1,170 @@
+use anyhow::{bail, ensure, Context};
 use itertools::Itertools;
+use std::env;
 
+/// Note: Run this daily solution with DEBUG=1 env var to print all reflection lines of each pattern
+/// that need at most one smudge fixed.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let patterns: Vec<_> = input
-        .split("\n\n")
-        .map(aoc::parse_char_grid)
-        .try_collect()?;
+    let patterns: Vec<_> = input.split("\n\n").map(Pattern::parse).try_collect()?;
+    debug_print_reflections(&patterns);
 
-    let ans_1: usize = patterns.iter().map(|p| summarize_pattern(p, 0)).sum();
-    let ans_2: usize = patterns.iter().map(|p| summarize_pattern(p, 1)).sum();
+    let ans_1 = summarize_patterns(&patterns, 0)?;
+    let ans_2 = summarize_patterns(&patterns, 1)?;
     println!("{ans_1} {ans_2}");
 
     Ok(())
 }
 
-fn summarize_pattern(pattern: &(Vec<Vec<char>>, usize, usize), expected_diff: usize) -> usize {
-    let (ref pattern, width, height) = *pattern;
-    for row in 1..height {
-        let count_row_diffs = |(row_a, row_b): (usize, usize)| {
-            pattern[row_a]
-                .iter()
-                .zip(pattern[row_b].iter())
-                .filter(|(tile_a, tile_b)| tile_a != tile_b)
-                .count()
+/// A pattern of ash and rocks, stored as bitmasks of rocks for each row and each column.
+struct Pattern {
+    rows: Vec<u64>,
+    cols: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    /// A horizontal line between rows.
+    Horizontal,
+    /// A vertical line between columns.
+    Vertical,
+}
+
+#[derive(Debug)]
+struct Reflection {
+    axis: Axis,
+    /// Number of rows above the line for horizontal lines, or columns to the left of the line for
+    /// vertical lines.
+    position: usize,
+    /// The (x, y) positions of the tiles that would need to be flipped for this to be a perfect
+    /// reflection. Only the tiles on the top or left side of the line are included.
+    smudges: Vec<(usize, usize)>,
+}
+
+impl Reflection {
+    fn summary(&self) -> usize {
+        match self.axis {
+            Axis::Horizontal => self.position * 100,
+            Axis::Vertical => self.position,
+        }
+    }
+}
+
+fn summarize_patterns(patterns: &[Pattern], smudges_count: u32) -> aoc::Result<usize> {
+    patterns
+        .iter()
+        .zip(1..)
+        .map(|(pattern, n)| {
+            let reflection = pattern
+                .find_reflection(smudges_count)
+                .with_context(|| format!("invalid pattern #{n}"))?;
+            Ok(reflection.summary())
+        })
+        .sum()
+}
+
+fn debug_print_reflections(patterns: &[Pattern]) {
+    if env::var("DEBUG").is_err() {
+        return;
+    }
+    for (pattern, n) in patterns.iter().zip(1..) {
+        println!("Pattern #{n}:");
+        for reflection in pattern.reflections().filter(|r| r.smudges.len() <= 1) {
+            let Reflection {
+                axis,
+                position,
+                smudges,
+            } = reflection;
+            println!("  {axis:?} line at {position}, smudges: {smudges:?}");
+        }
+    }
+    println!();
+}
+
+impl Pattern {
+    fn parse(s: &str) -> aoc::Result<Pattern> {
+        let (grid, width, height) = aoc::parse_grid(s, |ch| match ch {
+            '#' => Ok(true),
+            '.' => Ok(false),
+            _ => bail!("unexpected character '{ch}'"),
+        })?;
+        ensure!(width <= 64 && height <= 64, "pattern too big");
+        let to_bitmask = |bits: &mut dyn Iterator<Item = bool>| {
+            bits.zip(0..).map(|(bit, i)| (bit as u64) << i).sum()
         };
-        let diff_count: usize = (0..row).rev().zip(row..height).map(count_row_diffs).sum();
-        if diff_count == expected_diff {
-            return row * 100;
+        let rows = grid.iter().map(|row| to_bitmask(&mut row.iter().copied()));
+        let cols = (0..width).map(|x| to_bitmask(&mut grid.iter().map(|row| row[x])));
+        Ok(Pattern {
+            rows: rows.collect(),
+            cols: cols.collect(),
+        })
+    }
+
+    /// Returns all the possible reflection lines, both horizontal and vertical, with the smudges
+    /// that each one would need to fix.
+    fn reflections(&self) -> impl Iterator<Item = Reflection> + '_ {
+        let horizontal = (1..self.rows.len()).map(|pos| self.reflection(Axis::Horizontal, pos));
+        let vertical = (1..self.cols.len()).map(|pos| self.reflection(Axis::Vertical, pos));
+        horizontal.chain(vertical)
+    }
+
+    /// Finds the only reflection line with exactly the given number of smudges. Fails if there's
+    /// no such line, or if there's more than one.
+    fn find_reflection(&self, smudges_count: u32) -> aoc::Result<Reflection> {
+        let horizontal = (1..self.rows.len())
+            .filter(|&pos| count_smudges(&self.rows, pos) == smudges_count)
+            .map(|pos| (Axis::Horizontal, pos));
+        let vertical = (1..self.cols.len())
+            .filter(|&pos| count_smudges(&self.cols, pos) == smudges_count)
+            .map(|pos| (Axis::Vertical, pos));
+        let candidates = horizontal.chain(vertical).collect_vec();
+        match candidates[..] {
+            [(axis, position)] => Ok(self.reflection(axis, position)),
+            [] => bail!("no reflection line with {smudges_count} smudges found"),
+            _ => {
+                let lines = candidates
+                    .iter()
+                    .map(|(axis, position)| format!("{axis:?} {position}"))
+                    .join(", ");
+                bail!("multiple reflection lines with {smudges_count} smudges found: {lines}")
+            }
         }
     }
 
-    for col in 1..width {
-        let count_col_diffs = |(col_a, col_b)| {
-            pattern
-                .iter()
-                .filter(|row| row[col_a] != row[col_b])
-                .count()
+    fn reflection(&self, axis: Axis, position: usize) -> Reflection {
+        let smudges = match axis {
+            Axis::Horizontal => find_smudges(&self.rows, position)
+                .map(|(y, x)| (x, y))
+                .collect(),
+            Axis::Vertical => find_smudges(&self.cols, position).collect(),
         };
-        let diff_count: usize = (0..col).rev().zip(col..width).map(count_col_diffs).sum();
-        if diff_count == expected_diff {
-            return col;
+        Reflection {
+            axis,
+            position,
+            smudges,
         }
     }
+}
+
+/// Pairs up the lines mirrored by a reflection after the given number of lines.
+fn mirrored_pairs(lines: &[u64], position: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..position).rev().zip(position..lines.len())
+}
+
+/// Counts the differences between all mirrored lines, where each line is a bitmask.
+fn count_smudges(lines: &[u64], position: usize) -> u32 {
+    mirrored_pairs(lines, position)
+        .map(|(a, b)| (lines[a] ^ lines[b]).count_ones())
+        .sum()
+}
 
-    0
+/// Returns the (line index, bit index) of each difference between mirrored lines, on the lines
+/// before the reflection.
+fn find_smudges(lines: &[u64], position: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+    mirrored_pairs(lines, position).flat_map(|(a, b)| {
+        let diff = lines[a] ^ lines[b];
+        (0..64)
+            .filter(move |i| diff & (1 << i) != 0)
+            .map(move |i| (a, i))
+    })
 }
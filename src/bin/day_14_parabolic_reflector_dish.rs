@@ -1,86 +1,141 @@
-use std::collections::HashMap;
+use anyhow::bail;
 
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let (start_grid, width, height) = aoc::parse_char_grid(&input)?;
-    anyhow::ensure!(width == height, "grid must be square");
+    let start_platform = Platform::parse(&input)?;
 
     // Part 1
-    let mut grid = start_grid.clone();
-    tilt_platform(&mut grid, NORTH);
-    let north_beams_load_p1 = get_north_beams_load(&grid);
+    let mut platform = start_platform.clone();
+    platform.tilt(Dir::North);
+    let north_beams_load_p1 = platform.north_beams_load();
 
     // Part 2
-    let mut grid = start_grid.clone();
-    let mut remaining_spins = 1_000_000_000;
-    let mut grids_memo = HashMap::new();
-    while remaining_spins > 0 {
-        for dir in [NORTH, WEST, SOUTH, EAST] {
-            tilt_platform(&mut grid, dir);
-        }
-        remaining_spins -= 1;
-        if let Some(prev_remaining_spins) = grids_memo.get(&grid) {
-            let cycle_size = prev_remaining_spins - remaining_spins;
-            remaining_spins %= cycle_size;
-        } else {
-            grids_memo.insert(grid.clone(), remaining_spins);
-        }
-    }
-    let north_beams_load_p2 = get_north_beams_load(&grid);
+    let spin = |platform: &Platform| {
+        let mut platform = platform.clone();
+        platform.spin();
+        platform
+    };
+    let (_, _, platform) = aoc::cycle::state_after(start_platform, 1_000_000_000, spin);
+    let north_beams_load_p2 = platform.north_beams_load();
 
     println!("{north_beams_load_p1} {north_beams_load_p2}");
     Ok(())
 }
 
-type Grid = Vec<Vec<char>>;
-type Pos = (usize, usize);
-type Dir = (isize, isize);
+#[derive(Clone, Copy)]
+enum Dir {
+    North,
+    West,
+    South,
+    East,
+}
 
-const NORTH: Dir = (0, -1);
-const WEST: Dir = (-1, 0);
-const SOUTH: Dir = (0, 1);
-const EAST: Dir = (1, 0);
+/// The platform rocks, stored as one bitmask per row for round rocks and another one for square
+/// rocks, with the bit `x` representing column `x`.
+#[derive(Clone)]
+struct Platform {
+    round: Vec<u128>,
+    square: Vec<u128>,
+    /// Bitmask with all columns set.
+    row_mask: u128,
+}
 
-fn tilt_platform(grid: &mut Grid, (dx, dy): Dir) {
-    let size = grid.len() as isize;
-    let neg_dir = (-dx, -dy);
-    let mut line_start = (0, 0);
-    for _ in 0..size {
-        let off_x = if dx == 1 { size - 1 } else { 0 };
-        let off_y = if dy == 1 { size - 1 } else { 0 };
-        let mut pos = add(line_start, (off_x, off_y));
-        let mut last_empty = pos;
-        // We move on a vertical or horizontal line, going in the opposite of the given direction,
-        // and "rolling" the round rocks in the given direction "down" to `last_empty`, updating the
-        // latter as we go.
-        for _ in 0..size {
-            match at(grid, pos) {
-                '#' => last_empty = add(pos, neg_dir),
-                'O' => {
-                    *at(grid, pos) = '.';
-                    *at(grid, last_empty) = 'O';
-                    last_empty = add(last_empty, neg_dir)
+impl Platform {
+    fn parse(input: &str) -> aoc::Result<Platform> {
+        let (grid, width, _height) = aoc::parse_char_grid(input)?;
+        anyhow::ensure!(width <= 128, "platform can be at most 128 tiles wide");
+        let mut round = vec![0; grid.len()];
+        let mut square = vec![0; grid.len()];
+        for (y, row) in grid.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                match tile {
+                    'O' => round[y] |= 1 << x,
+                    '#' => square[y] |= 1 << x,
+                    '.' => {}
+                    _ => bail!("unexpected tile '{tile}'"),
                 }
-                _ => {}
             }
-            pos = add(pos, neg_dir);
         }
-        // Move start down if we're tilting horizontally, and right if tiling vertically.
-        line_start = add(line_start, (dy.abs(), dx.abs()));
+        let row_mask = u128::MAX.checked_shr(128 - width as u32).unwrap_or(0);
+        Ok(Platform {
+            round,
+            square,
+            row_mask,
+        })
     }
-}
 
-fn at(grid: &mut Grid, (x, y): Pos) -> &mut char {
-    &mut grid[y][x]
-}
+    fn spin(&mut self) {
+        for dir in [Dir::North, Dir::West, Dir::South, Dir::East] {
+            self.tilt(dir);
+        }
+    }
+
+    /// Rolls all round rocks in the given direction. All rocks that can move one tile are moved at
+    /// the same time, until no more rocks can move.
+    fn tilt(&mut self, dir: Dir) {
+        match dir {
+            Dir::North => self.tilt_vertically(|y| y.checked_sub(1)),
+            Dir::South => {
+                let height = self.round.len();
+                self.tilt_vertically(|y| Some(y + 1).filter(|&y| y < height))
+            }
+            Dir::West => self.tilt_horizontally(|row| row >> 1, |row| row << 1),
+            Dir::East => self.tilt_horizontally(|row| row << 1, |row| row >> 1),
+        }
+    }
 
-fn add((x, y): Pos, (dx, dy): Dir) -> Pos {
-    (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy))
+    fn tilt_vertically(&mut self, next_row: impl Fn(usize) -> Option<usize>) {
+        let mut moved = true;
+        while moved {
+            moved = false;
+            for y in 0..self.round.len() {
+                let Some(next_y) = next_row(y) else {
+                    continue;
+                };
+                let empty = !(self.round[next_y] | self.square[next_y]);
+                let movable = self.round[y] & empty;
+                if movable != 0 {
+                    self.round[y] &= !movable;
+                    self.round[next_y] |= movable;
+                    moved = true;
+                }
+            }
+        }
+    }
+
+    /// Tilts each row, using `forward` to shift a row bitmask in the tilt direction, and `backward`
+    /// to shift it in the opposite direction.
+    fn tilt_horizontally(
+        &mut self,
+        forward: impl Fn(u128) -> u128,
+        backward: impl Fn(u128) -> u128,
+    ) {
+        for (round, &square) in self.round.iter_mut().zip(self.square.iter()) {
+            loop {
+                let empty = !(*round | square) & self.row_mask;
+                // Rocks whose next tile in the tilt direction is empty.
+                let movable = *round & backward(empty);
+                if movable == 0 {
+                    break;
+                }
+                *round = (*round & !movable) | forward(movable);
+            }
+        }
+    }
+
+    fn north_beams_load(&self) -> usize {
+        self.round
+            .iter()
+            .zip((1..=self.round.len()).rev())
+            .map(|(row, row_load)| row.count_ones() as usize * row_load)
+            .sum()
+    }
 }
 
-fn get_north_beams_load(grid: &Grid) -> usize {
-    grid.iter()
-        .zip((1..=grid.len()).rev())
-        .map(|(row, row_load)| row.iter().filter(|&&tile| tile == 'O').count() * row_load)
-        .sum()
+/// Round rocks are the only thing that changes when tilting, so platforms are compared by those
+/// only.
+impl PartialEq for Platform {
+    fn eq(&self, other: &Self) -> bool {
+        self.round == other.round
+    }
 }
@@ -1,11 +1,60 @@
+use std::{env, fmt};
+
+/// Note: Run this daily solution with DEBUG=1 env var to print the state of the boxes after every
+/// step, and the focusing power of each lens at the end, like the puzzle description does.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
+    let debug = env::var("DEBUG").is_ok();
     let hash_sum: usize = input.trim_end().split(',').map(hash).sum();
 
-    let mut boxes = vec![vec![]; 256];
+    let mut library = LensLibrary::new();
     for step in input.trim_end().split(',') {
         let (label, step_type) = parse_step(step)?;
-        let box_lenses = &mut boxes[hash(label)];
+        library.apply(label, step_type);
+        if debug {
+            println!("After \"{step}\":\n{library}");
+        }
+    }
+
+    if debug {
+        for lens in library.lens_powers() {
+            println!("{lens}");
+        }
+        println!();
+    }
+    let total_focusing_power = library.total_focusing_power();
+
+    println!("{hash_sum} {total_focusing_power}");
+    Ok(())
+}
+
+enum StepType {
+    Remove,
+    Put(u64),
+}
+
+/// The HASHMAP: 256 boxes with an ordered list of (label, focal length) lenses each.
+struct LensLibrary<'a> {
+    boxes: Vec<Vec<(&'a str, u64)>>,
+}
+
+/// The focusing power of a single lens, and the numbers that make it up.
+struct LensPower<'a> {
+    label: &'a str,
+    box_index: usize,
+    slot: usize,
+    focal_length: u64,
+}
+
+impl<'a> LensLibrary<'a> {
+    fn new() -> LensLibrary<'a> {
+        LensLibrary {
+            boxes: vec![vec![]; 256],
+        }
+    }
+
+    fn apply(&mut self, label: &'a str, step_type: StepType) {
+        let box_lenses = &mut self.boxes[hash(label)];
         match step_type {
             StepType::Remove => box_lenses.retain(|&(l, _)| l != label),
             StepType::Put(focal_length) => {
@@ -18,20 +67,71 @@ fn main() -> aoc::Result<()> {
         }
     }
 
-    let mut total_focusing_power = 0;
-    for (lenses, box_index) in boxes.iter().zip(1..) {
-        for ((_l, focal_length), lens_index) in lenses.iter().zip(1..) {
-            total_focusing_power += box_index * lens_index * focal_length;
+    /// The lenses on the given box, in slot order.
+    fn box_lenses(&self, box_index: usize) -> &[(&'a str, u64)] {
+        &self.boxes[box_index]
+    }
+
+    fn lens_powers(&self) -> impl Iterator<Item = LensPower<'a>> + '_ {
+        self.boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(box_index, lenses)| {
+                lenses
+                    .iter()
+                    .zip(1..)
+                    .map(move |(&(label, focal_length), slot)| LensPower {
+                        label,
+                        box_index,
+                        slot,
+                        focal_length,
+                    })
+            })
+    }
+
+    fn total_focusing_power(&self) -> u64 {
+        self.lens_powers().map(|lens| lens.focusing_power()).sum()
+    }
+}
+
+impl fmt::Display for LensLibrary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for box_index in 0..self.boxes.len() {
+            let lenses = self.box_lenses(box_index);
+            if lenses.is_empty() {
+                continue;
+            }
+            write!(f, "Box {box_index}:")?;
+            for (label, focal_length) in lenses {
+                write!(f, " [{label} {focal_length}]")?;
+            }
+            writeln!(f)?;
         }
+        Ok(())
     }
+}
 
-    println!("{hash_sum} {total_focusing_power}");
-    Ok(())
+impl LensPower<'_> {
+    fn focusing_power(&self) -> u64 {
+        (self.box_index as u64 + 1) * self.slot as u64 * self.focal_length
+    }
 }
 
-enum StepType {
-    Remove,
-    Put(u64),
+impl fmt::Display for LensPower<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let LensPower {
+            label,
+            box_index,
+            slot,
+            focal_length,
+        } = self;
+        let power = self.focusing_power();
+        let box_num = box_index + 1;
+        write!(
+            f,
+            "{label}: {box_num} (box {box_index}) * {slot} (slot) * {focal_length} (focal length) = {power}"
+        )
+    }
 }
 
 fn parse_step(s: &str) -> aoc::Result<(&str, StepType)> {
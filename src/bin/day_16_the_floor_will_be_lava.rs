@@ -1,19 +1,54 @@
-use std::iter;
+use anyhow::{bail, ensure, Context};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, iter,
+};
 
+/// Note: Run this daily solution with CROSS_CHECK=1 env var to also count the energized tiles of
+/// every starting beam with a plain beam simulation, and check that both approaches agree.
+///
+/// Run with TILES=<file> env var to load extra tile definitions, in the same format as
+/// `PUZZLE_TILES`, which get added to the puzzle ones (or replace them, if they use the same
+/// character).
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let (grid, width, height) = aoc::parse_char_grid(&input)?;
-    let initial = count_energized_tiles(&grid, 0, 0, RIGHT)?;
-
-    let starting_beams = iter::empty()
-        .chain((0..height).map(|y| (width - 1, y, LEFT)))
-        .chain((0..height).map(|y| (0, y, RIGHT)))
-        .chain((0..width).map(|x| (x, height - 1, UP)))
-        .chain((0..width).map(|x| (x, 0, DOWN)));
-    let mut max = 0;
-    for (x, y, dir) in starting_beams {
-        max = max.max(count_energized_tiles(&grid, x, y, dir)?)
+    let cross_check = env::var("CROSS_CHECK").is_ok();
+
+    let mut definitions = parse_tile_definitions(PUZZLE_TILES)?;
+    if let Ok(path) = env::var("TILES") {
+        let config = fs::read_to_string(&path).with_context(|| format!("cannot read {path}"))?;
+        let extra_definitions = parse_tile_definitions(&config)
+            .with_context(|| format!("invalid tile definitions on {path}"))?;
+        definitions.extend(extra_definitions);
+    }
+    let contraption = Contraption::new(&grid, &definitions)?;
+    let graph = BeamGraph::new(&contraption);
+
+    let initial = graph.count_energized_tiles(&[(0, RIGHT)])[0];
+
+    let starting_beams: Vec<_> = iter::empty()
+        .chain((0..height).map(|y| (y * width + width - 1, LEFT)))
+        .chain((0..height).map(|y| (y * width, RIGHT)))
+        .chain((0..width).map(|x| ((height - 1) * width + x, UP)))
+        .chain((0..width).map(|x| (x, DOWN)))
+        .collect();
+    let counts = graph.count_energized_tiles(&starting_beams);
+    if cross_check {
+        starting_beams
+            .par_iter()
+            .zip(&counts)
+            .try_for_each(|(&beam, &count)| {
+                let simulated_count = count_energized_tiles(&contraption, beam);
+                ensure!(
+                    count == simulated_count,
+                    "energized tiles differ for beam {beam:?}: {count} != {simulated_count}"
+                );
+                Ok(())
+            })?;
     }
+    let max = counts.iter().max().copied().unwrap_or(0);
 
     println!("{initial} {max}");
     Ok(())
@@ -24,55 +59,445 @@ const UP: u32 = 1;
 const LEFT: u32 = 2;
 const DOWN: u32 = 4;
 const RIGHT: u32 = 8;
+const DIRECTIONS: [u32; 4] = [UP, LEFT, DOWN, RIGHT];
 
-fn count_energized_tiles(
-    grid: &[Vec<char>],
-    start_x: usize,
-    start_y: usize,
-    start_dir: u32,
-) -> aoc::Result<usize> {
-    let mut beams_grid = vec![vec![EMPTY; grid[0].len()]; grid.len()];
-    let mut beams = vec![(start_x, start_y, start_dir)];
-    while let Some(beam) = beams.pop() {
-        let (mut x, mut y, mut dir) = beam;
-        loop {
-            let Some(&ch) = grid.get(y).and_then(|row| row.get(x)) else {
-                // Beam goes out of bounds.
-                break;
+/// The puzzle tiles. Each line has a tile character followed by `<incoming>:<outgoing>` rules,
+/// where `<outgoing>` is a comma-separated list of directions. Beams coming from directions with no
+/// rule get absorbed, so a character alone on its line is an absorber. A character followed by
+/// `portal` is a portal, which teleports beams to the other tile with that same character, keeping
+/// their direction.
+const PUZZLE_TILES: &str = "\
+. up:up left:left down:down right:right
+/ up:right left:down down:left right:up
+\\ up:left left:up down:right right:down
+- up:left,right down:left,right left:left right:right
+| left:up,down right:up,down up:up down:down
+";
+
+#[derive(Clone, Copy)]
+enum TileDefinition {
+    /// The outgoing directions, as a bitmask, for each incoming direction.
+    Optic([u32; 4]),
+    Portal,
+}
+
+#[derive(Clone, Copy)]
+enum Tile {
+    /// The outgoing directions, as a bitmask, for each incoming direction.
+    Optic([u32; 4]),
+    /// Teleports beams to the tile with the given index.
+    Portal(usize),
+}
+
+/// The contraption grid, with its tiles stored by tile index (i.e. `y * width + x`).
+struct Contraption {
+    tiles: Vec<Tile>,
+    width: usize,
+    height: usize,
+}
+
+/// A beam entering a tile, given by its index, going in some direction.
+type Beam = (usize, u32);
+
+impl Contraption {
+    fn new(
+        grid: &[Vec<char>],
+        definitions: &HashMap<char, TileDefinition>,
+    ) -> aoc::Result<Contraption> {
+        let height = grid.len();
+        let width = grid.first().map_or(0, |row| row.len());
+        let mut tiles = Vec::with_capacity(width * height);
+        let mut portals: HashMap<char, Vec<usize>> = HashMap::new();
+        for &ch in grid.iter().flatten() {
+            let tile = match definitions.get(&ch) {
+                Some(TileDefinition::Optic(outgoing)) => Tile::Optic(*outgoing),
+                Some(TileDefinition::Portal) => {
+                    portals.entry(ch).or_default().push(tiles.len());
+                    // Gets linked to the other end once all tiles are known.
+                    Tile::Portal(tiles.len())
+                }
+                None => bail!("unexpected tile '{ch}'"),
+            };
+            tiles.push(tile);
+        }
+        for (ch, ends) in portals {
+            let &[a, b] = &ends[..] else {
+                bail!("portal '{ch}' appears {} times instead of 2", ends.len())
             };
-            if beams_grid[y][x] & dir != 0 {
+            tiles[a] = Tile::Portal(b);
+            tiles[b] = Tile::Portal(a);
+        }
+        Ok(Contraption {
+            tiles,
+            width,
+            height,
+        })
+    }
+
+    /// Returns where a beam entering the given tile comes out from, which is the same tile except
+    /// for portals, and the directions it comes out in, as a bitmask.
+    fn outgoing(&self, tile: usize, dir: u32) -> (usize, u32) {
+        match self.tiles[tile] {
+            Tile::Optic(outgoing) => (tile, outgoing[dir_index(dir)]),
+            Tile::Portal(other_end) => (other_end, dir),
+        }
+    }
+
+    /// The index of the next tile in the given direction, if it's inside the grid.
+    fn next_tile(&self, tile: usize, dir: u32) -> Option<usize> {
+        let (x, y) = (tile % self.width, tile / self.width);
+        match dir {
+            UP if y > 0 => Some(tile - self.width),
+            DOWN if y + 1 < self.height => Some(tile + self.width),
+            LEFT if x > 0 => Some(tile - 1),
+            RIGHT if x + 1 < self.width => Some(tile + 1),
+            _ => None,
+        }
+    }
+}
+
+fn dir_index(dir: u32) -> usize {
+    dir.trailing_zeros() as usize
+}
+
+/// Precomputed beam paths, to count the energized tiles of many starting beams without simulating
+/// the whole contraption for each one.
+///
+/// Beams only branch out when they get split, and a split always does the same thing regardless of
+/// where the beam came from. So splits are the nodes of a graph whose edges are the beam segments
+/// between them, and everything a split energizes is the union of the tiles of all the segments
+/// reachable from it. Splits on the same strongly connected component reach the same segments, so
+/// they get collapsed into a single node, and starting beams that end up on the same component
+/// share the work of collecting its energized tiles.
+struct BeamGraph<'a> {
+    contraption: &'a Contraption,
+    /// Split node indices. Splits are identified by the tile the beams come out from and their
+    /// outgoing directions, so the two sides of a splitter are the same node.
+    split_nodes: HashMap<(usize, u32), usize>,
+    /// Strongly connected component index of each split node.
+    node_components: Vec<usize>,
+    /// Tiles of each component's splits and the segments coming out of them.
+    component_tiles: Vec<Vec<usize>>,
+    /// The components directly reachable from each component, excluding itself.
+    component_successors: Vec<Vec<usize>>,
+    /// Bitsets of all the tiles reachable from big components.
+    reachable_cache: Vec<Option<Vec<u64>>>,
+}
+
+/// The path of a beam until it leaves the grid, gets absorbed, loops, or gets split.
+struct Segment {
+    /// Tile indices the beam goes through, in order. There may be repetitions.
+    tiles: Vec<usize>,
+    /// The split node at the end of the segment, if any.
+    split: Option<usize>,
+}
+
+impl<'a> BeamGraph<'a> {
+    fn new(contraption: &'a Contraption) -> BeamGraph<'a> {
+        let tiles_count = contraption.tiles.len();
+        let mut splits = vec![];
+        let mut split_nodes = HashMap::new();
+        for tile in 0..tiles_count {
+            for dir in DIRECTIONS {
+                let split = contraption.outgoing(tile, dir);
+                if split.1.count_ones() >= 2 {
+                    split_nodes.entry(split).or_insert_with(|| {
+                        splits.push(split);
+                        splits.len() - 1
+                    });
+                }
+            }
+        }
+        let mut graph = BeamGraph {
+            contraption,
+            split_nodes,
+            node_components: vec![],
+            component_tiles: vec![],
+            component_successors: vec![],
+            reachable_cache: vec![],
+        };
+
+        // The segments coming out of each split, traced in parallel.
+        let node_segments: Vec<Vec<_>> = splits
+            .par_iter()
+            .map(|&(tile, dirs)| {
+                let out_dirs = DIRECTIONS.into_iter().filter(|dir| dirs & dir != 0);
+                out_dirs
+                    .filter_map(|dir| Some((contraption.next_tile(tile, dir)?, dir)))
+                    .map(|beam| graph.trace_segment(beam))
+                    .collect()
+            })
+            .collect();
+        let successors: Vec<Vec<usize>> = node_segments
+            .iter()
+            .map(|segments| segments.iter().filter_map(|s| s.split).collect())
+            .collect();
+
+        let (node_components, components_count) = strongly_connected_components(&successors);
+        let mut component_tiles = vec![vec![]; components_count];
+        let mut component_successors = vec![vec![]; components_count];
+        for (node, &component) in node_components.iter().enumerate() {
+            let tiles = &mut component_tiles[component];
+            tiles.push(splits[node].0);
+            for segment in node_segments[node].iter() {
+                tiles.extend(&segment.tiles);
+            }
+            let next_components = successors[node].iter().map(|&next| node_components[next]);
+            component_successors[component].extend(next_components.filter(|&c| c != component));
+        }
+        for tiles in component_tiles
+            .iter_mut()
+            .chain(component_successors.iter_mut())
+        {
+            tiles.sort_unstable();
+            tiles.dedup();
+        }
+
+        graph.node_components = node_components;
+        graph.component_tiles = component_tiles;
+        graph.component_successors = component_successors;
+
+        // Cache the reachable tiles of components that have at least as many tiles as a bitset has
+        // words, so collecting the reachable tiles of other components can stop at them. There can
+        // be many of these when segments of different splits cross the same tiles, but each cached
+        // bitset takes no more memory than the tiles list of its component, so the cache never
+        // takes more memory than `component_tiles`. Components come in reverse topological order,
+        // so everything reachable from a component is already cached by the time we get to it.
+        graph.reachable_cache = vec![None; components_count];
+        for component in 0..components_count {
+            if graph.component_tiles[component].len() >= tiles_count.div_ceil(64) {
+                graph.reachable_cache[component] = Some(graph.reachable_tiles(component));
+            }
+        }
+        graph
+    }
+
+    /// Counts the energized tiles for each of the given starting beams.
+    fn count_energized_tiles(&self, starts: &[Beam]) -> Vec<usize> {
+        let segments: Vec<_> = starts
+            .par_iter()
+            .map(|&beam| self.trace_segment(beam))
+            .collect();
+
+        // Group starting beams by the component they end up on, so the energized tiles of each
+        // component only get collected once.
+        let mut component_starts = vec![vec![]; self.component_tiles.len()];
+        let mut counts = vec![0; starts.len()];
+        for (i, segment) in segments.iter().enumerate() {
+            match segment.split {
+                Some(node) => component_starts[self.node_components[node]].push(i),
+                None => counts[i] = segment.tiles.iter().collect::<HashSet<_>>().len(),
+            }
+        }
+
+        let segments = &segments;
+        let component_counts: Vec<_> = component_starts
+            .par_iter()
+            .enumerate()
+            .filter(|(_, starts)| !starts.is_empty())
+            .flat_map_iter(|(component, starts)| {
+                let reachable = self.reachable_tiles(component);
+                let reachable_count: usize =
+                    reachable.iter().map(|w| w.count_ones() as usize).sum();
+                starts.iter().map(move |&i| {
+                    let extra_tiles: HashSet<_> = segments[i]
+                        .tiles
+                        .iter()
+                        .filter(|&&tile| !has_tile(&reachable, tile))
+                        .collect();
+                    (i, reachable_count + extra_tiles.len())
+                })
+            })
+            .collect();
+        for (i, count) in component_counts {
+            counts[i] = count;
+        }
+        counts
+    }
+
+    /// Collects the tiles energized by a beam hitting any split of the given component, as a
+    /// bitset of tile indices.
+    fn reachable_tiles(&self, component: usize) -> Vec<u64> {
+        let mut tiles = vec![0; self.contraption.tiles.len().div_ceil(64)];
+        let mut visited = vec![false; self.component_tiles.len()];
+        visited[component] = true;
+        let mut stack = vec![component];
+        while let Some(component) = stack.pop() {
+            if let Some(cached_tiles) = &self.reachable_cache[component] {
+                for (word, cached_word) in tiles.iter_mut().zip(cached_tiles) {
+                    *word |= cached_word;
+                }
+                continue;
+            }
+            for &tile in self.component_tiles[component].iter() {
+                insert_tile(&mut tiles, tile);
+            }
+            for &next in self.component_successors[component].iter() {
+                if !visited[next] {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Follows a beam until it gets split, or it stops.
+    fn trace_segment(&self, start: Beam) -> Segment {
+        let (mut tile, mut dir) = start;
+        let mut tiles = vec![];
+        let mut visited = HashSet::new();
+        while visited.insert((tile, dir)) {
+            tiles.push(tile);
+            let (exit_tile, dirs) = self.contraption.outgoing(tile, dir);
+            if exit_tile != tile {
+                tiles.push(exit_tile);
+            }
+            if dirs.count_ones() >= 2 {
+                return Segment {
+                    tiles,
+                    split: Some(self.split_nodes[&(exit_tile, dirs)]),
+                };
+            }
+            // No outgoing directions means the beam got absorbed.
+            if dirs == EMPTY {
                 break;
             }
-            beams_grid[y][x] |= dir;
-            match (dir, ch) {
-                (_, '.') | (LEFT | RIGHT, '-') | (UP | DOWN, '|') => {}
-                // Reflections
-                (RIGHT, '/') | (LEFT, '\\') => dir = UP,
-                (RIGHT, '\\') | (LEFT, '/') => dir = DOWN,
-                (UP, '/') | (DOWN, '\\') => dir = RIGHT,
-                (UP, '\\') | (DOWN, '/') => dir = LEFT,
-                // Splits
-                (LEFT | RIGHT, '|') => {
-                    beams.push((x, y.wrapping_sub(1), UP));
-                    beams.push((x, y + 1, DOWN));
-                    break;
+            dir = dirs;
+            let Some(next_tile) = self.contraption.next_tile(exit_tile, dir) else {
+                break;
+            };
+            tile = next_tile;
+        }
+        Segment { tiles, split: None }
+    }
+}
+
+fn insert_tile(bitset: &mut [u64], tile: usize) {
+    bitset[tile / 64] |= 1 << (tile % 64);
+}
+
+fn has_tile(bitset: &[u64], tile: usize) -> bool {
+    bitset[tile / 64] & (1 << (tile % 64)) != 0
+}
+
+/// Tarjan's strongly connected components algorithm, with an explicit stack instead of recursion
+/// so big graphs don't overflow the call stack. Returns the component index of each node, and the
+/// number of components. Components are numbered in reverse topological order: the successors of a
+/// node are always on the same component or on a lower-numbered one.
+fn strongly_connected_components(successors: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    const UNVISITED: usize = usize::MAX;
+    let nodes_count = successors.len();
+    let mut index = vec![UNVISITED; nodes_count];
+    let mut low_link = vec![0; nodes_count];
+    let mut on_stack = vec![false; nodes_count];
+    let mut components = vec![0; nodes_count];
+    let mut components_count = 0;
+    let mut next_index = 0;
+    let mut stack = vec![];
+    // The DFS path, as (node, index of next successor to visit) pairs.
+    let mut path = vec![];
+
+    for root in 0..nodes_count {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        path.push((root, 0));
+        while let Some((node, successor_index)) = path.pop() {
+            if successor_index == 0 {
+                index[node] = next_index;
+                low_link[node] = next_index;
+                next_index += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+            if let Some(&next) = successors[node].get(successor_index) {
+                path.push((node, successor_index + 1));
+                if index[next] == UNVISITED {
+                    path.push((next, 0));
+                } else if on_stack[next] {
+                    low_link[node] = low_link[node].min(index[next]);
                 }
-                (UP | DOWN, '-') => {
-                    beams.push((x.wrapping_sub(1), y, LEFT));
-                    beams.push((x + 1, y, RIGHT));
-                    break;
+                continue;
+            }
+            // All successors visited.
+            if low_link[node] == index[node] {
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    components[member] = components_count;
+                    if member == node {
+                        break;
+                    }
                 }
-                _ => anyhow::bail!("unexpected tile '{ch}' in direction {dir}"),
+                components_count += 1;
             }
-            match dir {
-                LEFT => x = x.wrapping_sub(1),
-                RIGHT => x += 1,
-                UP => y = y.wrapping_sub(1),
-                DOWN => y += 1,
-                _ => unreachable!(),
+            if let Some(&(parent, _)) = path.last() {
+                low_link[parent] = low_link[parent].min(low_link[node]);
+            }
+        }
+    }
+    (components, components_count)
+}
+
+/// Plain beam simulation, following all beams from the starting one.
+fn count_energized_tiles(contraption: &Contraption, start: Beam) -> usize {
+    let tiles_count = contraption.tiles.len();
+    let mut beams_grid = vec![EMPTY; tiles_count];
+    let mut energized = vec![false; tiles_count];
+    let mut beams = vec![start];
+    while let Some((tile, dir)) = beams.pop() {
+        if beams_grid[tile] & dir != 0 {
+            continue;
+        }
+        beams_grid[tile] |= dir;
+        energized[tile] = true;
+        let (exit_tile, dirs) = contraption.outgoing(tile, dir);
+        energized[exit_tile] = true;
+        for out_dir in DIRECTIONS.into_iter().filter(|out_dir| dirs & out_dir != 0) {
+            if let Some(next_tile) = contraption.next_tile(exit_tile, out_dir) {
+                beams.push((next_tile, out_dir));
             }
         }
     }
-    let energized_tiles = beams_grid.iter().flatten().filter(|beams| **beams != 0);
-    Ok(energized_tiles.count())
+    energized.iter().filter(|&&e| e).count()
+}
+
+fn parse_tile_definitions(s: &str) -> aoc::Result<HashMap<char, TileDefinition>> {
+    let mut definitions = HashMap::new();
+    for line in s.lines().filter(|line| !line.trim().is_empty()) {
+        let mut chars = line.chars();
+        let ch = chars.next().context("empty line")?;
+        let rules = chars.as_str();
+        ensure!(
+            !ch.is_whitespace() && (rules.is_empty() || rules.starts_with(char::is_whitespace)),
+            "invalid tile definition '{line}'"
+        );
+        let definition = if rules.trim() == "portal" {
+            TileDefinition::Portal
+        } else {
+            let mut outgoing = [EMPTY; 4];
+            for rule in rules.split_whitespace() {
+                let (incoming, outgoing_dirs) = rule
+                    .split_once(':')
+                    .with_context(|| format!("invalid rule '{rule}' for tile '{ch}'"))?;
+                for dir in outgoing_dirs.split(',') {
+                    outgoing[dir_index(parse_direction(incoming)?)] |= parse_direction(dir)?;
+                }
+            }
+            TileDefinition::Optic(outgoing)
+        };
+        definitions.insert(ch, definition);
+    }
+    Ok(definitions)
+}
+
+fn parse_direction(s: &str) -> aoc::Result<u32> {
+    let dir = match s {
+        "up" => UP,
+        "left" => LEFT,
+        "down" => DOWN,
+        "right" => RIGHT,
+        _ => bail!("invalid direction '{s}'"),
+    };
+    Ok(dir)
 }
@@ -1,83 +1,275 @@
-use anyhow::Context;
-use pathfinding::directed::dijkstra::dijkstra;
+use anyhow::{bail, ensure, Context};
 use std::env;
 
-/// Note: Run this daily solution with DEBUG=1 env var to print the shortest path in the terminal.
+/// Note: Run this daily solution with DEBUG=1 env var to print the shortest path in the terminal,
+/// or with PATH_TABLE=1 to also print the heat loss of each step of the path.
+///
+/// Run with START=<x>,<y> and/or GOAL=<x>,<y> env vars to route the crucibles between other city
+/// blocks than the top-left and bottom-right ones, and with TURN_COST=<n> to add some extra heat
+/// loss every time a crucible turns. Blocks marked with '#' on the map can't be crossed.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let (city, w, h) = aoc::parse_grid(&input, |ch| {
-        ch.to_digit(10).context("unexpected non-digit character")
+        if ch == '#' {
+            return Ok(None);
+        }
+        let heat_loss = ch.to_digit(10).context("unexpected non-digit character")?;
+        Ok(Some(heat_loss))
     })?;
+    let debug = env::var("DEBUG").is_ok();
+    let path_table = env::var("PATH_TABLE").is_ok();
+    let start = parse_position_var("START")?.unwrap_or((0, 0));
+    let goal = parse_position_var("GOAL")?.unwrap_or((w.saturating_sub(1), h.saturating_sub(1)));
+    let turn_cost = match env::var("TURN_COST") {
+        Ok(n) => n.parse().context("TURN_COST must be a number")?,
+        Err(_) => 0,
+    };
+    ensure!(
+        turn_cost <= MAX_TURN_COST,
+        "TURN_COST must be at most {MAX_TURN_COST}"
+    );
 
-    let min_heat_loss_p1 = find_min_heat_loss(&city, w, h, 0, 3)?;
-    let min_heat_loss_p2 = find_min_heat_loss(&city, w, h, 4, 10)?;
+    let find_min_heat_loss = |min_straight_len, max_straight_len| -> aoc::Result<u64> {
+        let rules = CrucibleRules {
+            min_straight_len,
+            max_straight_len,
+            turn_cost,
+        };
+        let route = find_min_heat_loss_route(&city, start, goal, &rules)?;
+        if debug || path_table {
+            debug_print_path(&city, &route);
+        }
+        if path_table {
+            print_path_table(&route);
+        }
+        Ok(route.heat_loss)
+    };
+
+    let min_heat_loss_p1 = find_min_heat_loss(0, 3)?;
+    let min_heat_loss_p2 = find_min_heat_loss(4, 10)?;
     println!("{min_heat_loss_p1} {min_heat_loss_p2}");
     Ok(())
 }
 
-type Grid = Vec<Vec<u32>>;
-type Node = ((usize, usize), (isize, isize), u32);
+/// Dial's algorithm needs a bucket for each possible edge cost, so turn costs can't get too big.
+/// This also keeps heat losses plus turn costs well within a u32.
+const MAX_TURN_COST: u32 = 1_000_000;
 
-fn find_min_heat_loss(
-    city: &Grid,
-    width: usize,
-    height: usize,
+/// The city map, with the heat loss of each block, or `None` for blocks that can't be crossed.
+type Grid = Vec<Vec<Option<u32>>>;
+type Position = (usize, usize);
+
+struct CrucibleRules {
     min_straight_len: u32,
     max_straight_len: u32,
-) -> aoc::Result<u32> {
-    let start: Node = ((0, 0), (0, 0), 0);
-    let success = |&(pos, _d, straight_len): &Node| {
-        pos == (width - 1, height - 1) && straight_len >= min_straight_len
+    /// Extra heat loss every time the crucible turns.
+    turn_cost: u32,
+}
+
+/// Crucible state: position, and the axis it moved along to get there, with 0 for horizontal and 1
+/// for vertical, i.e. the direction index on `DIRS` modulo 2. Crucibles move straight for several
+/// blocks at once, so the next move is always a turn onto the other axis, and there's no need to
+/// keep track of how far it has moved straight, or which way it was going along the axis.
+type State = (usize, usize, usize);
+
+const DIRS: [(isize, isize); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const DIR_CHARS: [char; 4] = ['>', 'v', '<', '^'];
+
+struct Route {
+    steps: Vec<RouteStep>,
+    heat_loss: u64,
+}
+
+struct RouteStep {
+    pos: Position,
+    /// The direction index on `DIRS` the crucible moved in to get here, or `None` for the start.
+    dir: Option<usize>,
+    /// Heat lost on this step, including the turn cost.
+    heat_loss: u32,
+    turned: bool,
+}
+
+/// Numbers the crucible states densely, so the shortest path search can use plain arrays instead
+/// of hashing states. The last index is the start state, where the crucible hasn't moved yet, so
+/// it can go in any direction, and its first move isn't a turn.
+struct StateSpace {
+    width: usize,
+    height: usize,
+}
+
+impl StateSpace {
+    fn count(&self) -> usize {
+        self.width * self.height * 2 + 1
+    }
+
+    fn start(&self) -> usize {
+        self.count() - 1
+    }
+
+    fn index(&self, (x, y, axis): State) -> usize {
+        (y * self.width + x) * 2 + axis
+    }
+
+    fn state(&self, index: usize) -> State {
+        let tile = index / 2;
+        (tile % self.width, tile / self.width, index % 2)
+    }
+}
+
+fn find_min_heat_loss_route(
+    city: &Grid,
+    start: Position,
+    goal: Position,
+    rules: &CrucibleRules,
+) -> aoc::Result<Route> {
+    let block = |(x, y): Position| city.get(y).and_then(|row| row.get(x));
+    for (name, pos) in [("start", start), ("goal", goal)] {
+        match block(pos) {
+            None => bail!("{name} position {pos:?} is outside the city"),
+            Some(None) => bail!("{name} position {pos:?} is on a block that can't be crossed"),
+            Some(Some(_)) => {}
+        }
+    }
+
+    let width = city.first().map_or(0, |row| row.len());
+    let CrucibleRules {
+        min_straight_len,
+        max_straight_len,
+        turn_cost,
+    } = *rules;
+    let states = &StateSpace {
+        width,
+        height: city.len(),
     };
-    let successors = |&((x, y), dir, straight_len): &Node| {
-        let dirs = [(1, 0), (-1, 0), (0, 1), (0, -1)];
-        dirs.into_iter().filter_map(move |(dx, dy)| {
-            let (nx, ny) = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
-            let cost = *city.get(ny)?.get(nx)?;
-            if dir == (-dx, -dy) {
-                return None;
-            }
-            if dir != (0, 0) && (dx, dy) != dir && straight_len < min_straight_len {
-                return None;
-            }
-            let neighbor_straight_len = if (dx, dy) == dir { straight_len + 1 } else { 1 };
-            if neighbor_straight_len > max_straight_len {
-                return None;
-            }
-            Some((((nx, ny), (dx, dy), neighbor_straight_len), cost))
-        })
+    let start_index = states.start();
+    let position = |index| {
+        if index == start_index {
+            start
+        } else {
+            let (x, y, _axis) = states.state(index);
+            (x, y)
+        }
+    };
+    let success =
+        |index| position(index) == goal && (index != start_index || min_straight_len == 0);
+    let successors = |index| {
+        let (x, y) = position(index);
+        let axis = (index != start_index).then(|| states.state(index).2);
+        let turns = axis.is_some();
+        DIRS.iter()
+            .enumerate()
+            .filter(move |&(dir, _)| Some(dir % 2) != axis)
+            .flat_map(move |(dir, &(dx, dy))| {
+                // Move straight from 1 to max_straight_len blocks, adding up heat losses, until
+                // going out of the city or hitting a block that can't be crossed.
+                (1..=max_straight_len)
+                    .scan(((x, y), 0), move |(pos, heat_loss), straight_len| {
+                        *pos = (pos.0.wrapping_add_signed(dx), pos.1.wrapping_add_signed(dy));
+                        *heat_loss += (*block(*pos)?)?;
+                        Some((straight_len, *pos, *heat_loss))
+                    })
+                    .filter(move |&(straight_len, _, _)| straight_len >= min_straight_len)
+                    .map(move |(_, (nx, ny), heat_loss)| {
+                        let cost = heat_loss + if turns { turn_cost } else { 0 };
+                        (states.index((nx, ny, dir % 2)), cost)
+                    })
+            })
     };
 
-    let (path, min_heat_loss) = dijkstra(&start, successors, success)
-        .context("couldn't find a path to the machine parts factory")?;
+    let max_edge_cost = 9 * max_straight_len + turn_cost;
+    let (path, heat_loss) = aoc::shortest_path::dial(
+        states.count(),
+        max_edge_cost,
+        [start_index],
+        successors,
+        success,
+    )?
+    .with_context(|| format!("couldn't find a path from {start:?} to {goal:?}"))?;
 
-    debug_print_path(city, &path);
+    // Each move of the path goes straight for several blocks, so add a step for each of them.
+    let mut steps = vec![RouteStep {
+        pos: start,
+        dir: None,
+        heat_loss: 0,
+        turned: false,
+    }];
+    for (&from, &to) in path.iter().zip(&path[1..]) {
+        let (mut pos, to_pos) = (position(from), position(to));
+        let delta = (
+            (to_pos.0 as isize - pos.0 as isize).signum(),
+            (to_pos.1 as isize - pos.1 as isize).signum(),
+        );
+        let dir = DIRS
+            .iter()
+            .position(|&d| d == delta)
+            .context("path moves diagonally")?;
+        let mut turned = from != start_index;
+        while pos != to_pos {
+            pos = (
+                pos.0.wrapping_add_signed(delta.0),
+                pos.1.wrapping_add_signed(delta.1),
+            );
+            let block_heat_loss =
+                city[pos.1][pos.0].context("path goes through a forbidden block")?;
+            steps.push(RouteStep {
+                pos,
+                dir: Some(dir),
+                heat_loss: block_heat_loss + if turned { turn_cost } else { 0 },
+                turned,
+            });
+            turned = false;
+        }
+    }
+    ensure!(
+        steps.iter().map(|s| s.heat_loss as u64).sum::<u64>() == heat_loss,
+        "path steps don't add up to the total heat loss"
+    );
+    Ok(Route { steps, heat_loss })
+}
 
-    Ok(min_heat_loss)
+fn parse_position_var(name: &str) -> aoc::Result<Option<Position>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    let (x, y) = value
+        .split_once(',')
+        .with_context(|| format!("{name} must be <x>,<y>"))?;
+    let coord = |s: &str| {
+        s.trim()
+            .parse()
+            .with_context(|| format!("{name} must be <x>,<y>"))
+    };
+    Ok(Some((coord(x)?, coord(y)?)))
 }
 
-fn debug_print_path(city: &Grid, path: &[Node]) {
-    if env::var("DEBUG").is_err() {
-        return;
-    }
+fn debug_print_path(city: &Grid, route: &Route) {
     for (y, row) in city.iter().enumerate() {
         let mut line = String::new();
         for (x, block) in row.iter().enumerate() {
-            if let Some((_, dir, _)) = path.iter().find(|&&(pos, ..)| pos == (x, y)) {
-                let ch = match dir {
-                    (0, 0) => 'S',
-                    (-1, 0) => '<',
-                    (1, 0) => '>',
-                    (0, -1) => '^',
-                    (0, 1) => 'v',
-                    _ => unreachable!(),
-                };
+            if let Some(step) = route.steps.iter().find(|step| step.pos == (x, y)) {
+                let ch = step.dir.map_or('S', |dir| DIR_CHARS[dir]);
                 line.push_str(&format!("\x1b[34;1m{ch}\x1b[0m"));
+            } else if let Some(heat_loss) = block {
+                line.push_str(&heat_loss.to_string());
             } else {
-                line.push_str(&block.to_string());
+                line.push('#');
             }
         }
         println!("{line}");
     }
     println!();
 }
+
+fn print_path_table(route: &Route) {
+    println!("step     x     y  dir  heat loss  total");
+    let mut total = 0;
+    for (i, step) in route.steps.iter().enumerate() {
+        total += step.heat_loss as u64;
+        let (x, y) = step.pos;
+        let dir = step.dir.map_or('S', |dir| DIR_CHARS[dir]);
+        let turn_note = if step.turned { " (turn)" } else { "" };
+        let heat_loss = format!("{}{turn_note}", step.heat_loss);
+        println!("{i:>4} {x:>5} {y:>5}    {dir} {heat_loss:>10} {total:>6}");
+    }
+    println!();
+}
@@ -1,12 +1,27 @@
 use anyhow::{bail, Context};
+use aoc::polygon;
 use itertools::Itertools;
+use std::{env, fmt::Write, fs};
 
+/// Note: Run this daily solution with SVG=<prefix> env var to draw the part 1 and part 2 dig plans
+/// into <prefix>_p1.svg and <prefix>_p2.svg files.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let dig_plan_p1: Vec<_> = input.lines().map(parse_instruction_p1).try_collect()?;
     let dig_plan_p2: Vec<_> = input.lines().map(parse_instruction_p2).try_collect()?;
-    let lagoon_area_p1 = get_lagoon_area(&dig_plan_p1);
-    let lagoon_area_p2 = get_lagoon_area(&dig_plan_p2);
+
+    // Drawn before validating the dig plans, so broken ones can be looked at too.
+    if let Ok(prefix) = env::var("SVG") {
+        for (part, dig_plan) in [(1, &dig_plan_p1), (2, &dig_plan_p2)] {
+            let file = format!("{prefix}_p{part}.svg");
+            let title = format!("Lavaduct Lagoon, part {part} dig plan");
+            let svg = render_dig_plan_svg(&build_polygon(dig_plan), &title)?;
+            fs::write(&file, svg).with_context(|| format!("could not write SVG file '{file}'"))?;
+        }
+    }
+
+    let lagoon_area_p1 = get_lagoon_area(&dig_plan_p1).context("invalid part 1 dig plan")?;
+    let lagoon_area_p2 = get_lagoon_area(&dig_plan_p2).context("invalid part 2 dig plan")?;
     println!("{lagoon_area_p1} {lagoon_area_p2}");
     Ok(())
 }
@@ -19,14 +34,17 @@ enum Dir {
     Right,
 }
 
-fn get_lagoon_area(dig_plan: &[(Dir, i64)]) -> i64 {
-    let polygon = build_polygon(dig_plan);
-    let polygon_area = get_polygon_area(&polygon);
-    let polygon_perimeter: i64 = dig_plan.iter().map(|(_, c)| *c).sum();
-    // The polygon area doesn't take into account the "thickness" of the trench, which adds this
-    // extra area based on the polygon perimeter. Check sample_square.txt file for an easy to
-    // understand example of this.
-    polygon_area + polygon_perimeter / 2 + 1
+/// Calculates the area of the lagoon, including the trench around it. Fails if the dig plan doesn't
+/// end where it started, or if the trench crosses itself.
+fn get_lagoon_area(dig_plan: &[(Dir, i64)]) -> aoc::Result<u128> {
+    let path = build_polygon(dig_plan);
+    let vertices = polygon::close_path(&path)?;
+    // Edge #k of the polygon is dug by the kth instruction, so errors point to the right lines.
+    polygon::check_simple(vertices)?;
+    // The polygon area doesn't take into account the "thickness" of the trench. Counting lattice
+    // points instead, the lagoon is made of the points inside the polygon plus the ones on its
+    // boundary. Check sample_square.txt file for an easy to understand example of this.
+    Ok(polygon::interior_points(vertices) + polygon::boundary_points(vertices))
 }
 
 fn build_polygon(dig_plan: &[(Dir, i64)]) -> Vec<(i64, i64)> {
@@ -44,15 +62,60 @@ fn build_polygon(dig_plan: &[(Dir, i64)]) -> Vec<(i64, i64)> {
     polygon
 }
 
-/// Calculates the area of a polygon using the Shoelace formula.
-/// See https://en.wikipedia.org/wiki/Shoelace_formula
-fn get_polygon_area(polygon: &[(i64, i64)]) -> i64 {
-    let twice_area: i64 = polygon
+/// Renders the dig plan polygon as an SVG image scaled to fit in an 800x800 square. The trench is
+/// drawn as a 1 meter wide line centered on the polygon edges, like the cubes that get dug out, so
+/// it sticks out of the polygon by half a meter (or by at least a pixel on big plans).
+fn render_dig_plan_svg(polygon: &[(i64, i64)], title: &str) -> aoc::Result<String> {
+    const SIZE: f64 = 800.0;
+    const MARGIN: f64 = 20.0;
+    let (min_x, max_x) = polygon
         .iter()
-        .tuple_windows()
-        .map(|((x1, y1), (x2, y2))| x1 * y2 - x2 * y1)
-        .sum();
-    twice_area / 2
+        .map(|&(x, _)| x)
+        .minmax()
+        .into_option()
+        .context("empty dig plan")?;
+    let (min_y, max_y) = polygon
+        .iter()
+        .map(|&(_, y)| y)
+        .minmax()
+        .into_option()
+        .context("empty dig plan")?;
+    // Including the half meter of trench on each side.
+    let (width, height) = ((max_x - min_x) as f64 + 1.0, (max_y - min_y) as f64 + 1.0);
+    let scale = (SIZE - 2.0 * MARGIN) / width.max(height);
+    let to_svg = |(x, y): (i64, i64)| {
+        let svg_x = MARGIN + ((x - min_x) as f64 + 0.5) * scale;
+        let svg_y = MARGIN + ((y - min_y) as f64 + 0.5) * scale;
+        (svg_x, svg_y)
+    };
+    let (svg_width, svg_height) = (width * scale + 2.0 * MARGIN, height * scale + 2.0 * MARGIN);
+    let trench_width = scale.max(1.0);
+    let points = polygon
+        .iter()
+        .map(|&point| {
+            let (x, y) = to_svg(point);
+            format!("{x:.2},{y:.2}")
+        })
+        .join(" ");
+    let (start_x, start_y) = to_svg(polygon[0]);
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{svg_width:.0}" height="{svg_height:.0}">"#
+    )?;
+    writeln!(svg, "<title>{title}</title>")?;
+    writeln!(svg, r##"<rect width="100%" height="100%" fill="#111"/>"##)?;
+    writeln!(
+        svg,
+        r##"<polygon points="{points}" fill="#246" stroke="#4af" stroke-width="{trench_width:.2}" stroke-linejoin="miter"/>"##
+    )?;
+    writeln!(
+        svg,
+        r##"<circle cx="{start_x:.2}" cy="{start_y:.2}" r="5" fill="#f44"/>"##
+    )?;
+    writeln!(svg, "</svg>")?;
+    Ok(svg)
 }
 
 fn parse_instruction_p1(s: &str) -> aoc::Result<(Dir, i64)> {
@@ -1,237 +1,986 @@
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, ensure, Context};
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
+};
 
+/// Note: Besides the puzzle's `<` and `>` comparisons on the `xmas` categories, rule conditions
+/// can use any category name, the `<=`, `>=`, `==` and `!=` operators, and combine comparisons
+/// with `&&`, `||`, `!` and parentheses. E.g. `px{(a<2006&&m!=0)||!(x>=10):qkq,R}`.
+///
+/// Workflows are checked before processing any parts: references to undefined workflows and cycles
+/// are errors, while workflows that can't be reached from `in` and rules that can never match only
+/// print warnings.
+///
+/// Run with SIMPLIFY=<file> env var to write an equivalent, simplified, version of the workflows
+/// into a file, using only the puzzle's `<` and `>` comparisons, along with the same parts. Run
+/// with EQUIVALENT_TO=<file> to check whether the workflows accept the same parts as the ones on
+/// another file.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let (workflows, parts) = parse_input(&input)?;
-    let ratings_sum: u64 = parts
-        .iter()
-        .filter(|p| process_part(p, &workflows))
-        .map(|p| p.iter().sum::<u64>())
-        .sum();
-    let accept_combinations = get_accept_ratings_combinations(&workflows);
+    let (workflows_input, parts_input) = input.split_once("\n\n").context("invalid input")?;
+    let mut tree = load_workflows(workflows_input)?;
+    let parts: Vec<_> = parts_input.lines().map(parse_part).try_collect()?;
+    // Categories that only appear on parts are also part of the ratings combinations.
+    for (category, _) in parts.iter().flatten() {
+        tree.category_index(category);
+    }
+
+    if let Ok(file) = env::var("EQUIVALENT_TO") {
+        let other_input = fs::read_to_string(&file)
+            .with_context(|| format!("could not read workflows file '{file}'"))?;
+        let other_workflows_input = other_input
+            .split_once("\n\n")
+            .map_or(&*other_input, |(w, _)| w);
+        let other_tree = load_workflows(other_workflows_input)
+            .with_context(|| format!("invalid workflows file '{file}'"))?;
+        check_equivalent(&tree, &other_tree)
+            .with_context(|| format!("workflows are not equivalent to the ones on '{file}'"))?;
+        println!("workflows are equivalent to the ones on '{file}'");
+    }
+    if let Ok(file) = env::var("SIMPLIFY") {
+        let simplified = simplify_workflows(&tree);
+        // Better safe than sorry.
+        let simplified_tree = load_workflows(&simplified)?;
+        check_equivalent(&tree, &simplified_tree)
+            .context("simplified workflows are not equivalent to the original ones")?;
+        fs::write(&file, format!("{simplified}\n{parts_input}"))
+            .with_context(|| format!("could not write workflows file '{file}'"))?;
+    }
+
+    let mut ratings_sum = 0;
+    for (part, n) in parts.iter().zip(1..) {
+        let ratings = tree
+            .part_ratings(part)
+            .with_context(|| format!("invalid part #{n}"))?;
+        if process_part(&ratings, &tree) {
+            ratings_sum += part.iter().map(|(_, rating)| rating).sum::<u64>();
+        }
+    }
+    let accept_combinations = get_accept_ratings_combinations(&tree)?;
     println!("{ratings_sum} {accept_combinations}");
     Ok(())
 }
 
-type Part = [u64; 4];
+/// The ratings of a part, by category name.
+type Part<'a> = Vec<(&'a str, u64)>;
 
-struct Workflow {
-    rules: Vec<Rule>,
-    fallback: Output,
+/// The ratings of a part, by category index on the decision tree.
+type Ratings = Vec<u64>;
+
+/// The workflows by ID, in input order.
+type Workflows<'a> = Vec<(&'a str, Workflow<'a>)>;
+
+struct Workflow<'a> {
+    rules: Vec<Rule<'a>>,
+    fallback: Output<'a>,
 }
 
-struct Rule {
-    condition: Condition,
-    output: Output,
+struct Rule<'a> {
+    condition: Expr<'a>,
+    output: Output<'a>,
 }
 
-struct Condition {
-    category_index: usize,
-    op: Operator,
-    value: u64,
+/// A rule condition.
+enum Expr<'a> {
+    Compare {
+        category: &'a str,
+        op: Operator,
+        value: u64,
+    },
+    Not(Box<Expr<'a>>),
+    And(Box<Expr<'a>>, Box<Expr<'a>>),
+    Or(Box<Expr<'a>>, Box<Expr<'a>>),
 }
 
+#[derive(Clone, Copy)]
 enum Operator {
-    Gt,
     Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
 }
 
-type RatingsIntervals = [(u64, u64); 4];
+/// Inclusive (start, end) intervals of ratings, by category index.
+type RatingsIntervals = Vec<(u64, u64)>;
 
-enum Output {
+#[derive(Clone, Copy)]
+enum Output<'a> {
     Accept,
     Reject,
-    Workflow(String),
+    Workflow(&'a str),
+}
+
+/// The workflows compiled into a graph of nodes that either test a single rating against a
+/// threshold, or give an output. All rule conditions, no matter how complex, boil down to these
+/// threshold tests, so processing parts and collecting accepted rating intervals only need to deal
+/// with this one kind of test.
+struct DecisionTree<'a> {
+    nodes: Vec<Node<'a>>,
+    /// The root node of each workflow.
+    roots: HashMap<&'a str, usize>,
+    /// The output nodes of each workflow's rules, ending with the fallback one.
+    outputs: HashMap<&'a str, Vec<usize>>,
+    categories: Vec<&'a str>,
 }
 
-fn process_part(part: &Part, workflows: &HashMap<&str, Workflow>) -> bool {
-    let mut workflow_id = "in";
+enum Node<'a> {
+    /// Goes to the `below` node if the rating for the category is less than the threshold, or to
+    /// the `above` node otherwise.
+    Test {
+        category: usize,
+        threshold: u64,
+        below: usize,
+        above: usize,
+    },
+    Output(Output<'a>),
+}
+
+/// Expects workflows that passed `check_workflows`, so it doesn't loop forever.
+fn process_part(ratings: &Ratings, tree: &DecisionTree) -> bool {
+    let mut node = tree.roots["in"];
     loop {
-        let Some(workflow) = workflows.get(workflow_id) else {
-            eprintln!("workflow ID '{workflow_id}' not found; rejecting part");
-            return false;
-        };
-        let output = workflow.process(part);
-        match output {
-            Output::Accept => return true,
-            Output::Reject => return false,
-            Output::Workflow(id) => workflow_id = id,
+        match tree.nodes[node] {
+            Node::Test {
+                category,
+                threshold,
+                below,
+                above,
+            } => {
+                node = if ratings[category] < threshold {
+                    below
+                } else {
+                    above
+                }
+            }
+            Node::Output(Output::Accept) => return true,
+            Node::Output(Output::Reject) => return false,
+            Node::Output(Output::Workflow(id)) => node = tree.roots[id],
         }
     }
 }
 
-fn get_accept_ratings_combinations(workflows: &HashMap<&str, Workflow>) -> u64 {
-    let start_intervals = [(1, 4000); 4];
-    let accept_intervals = collect_accept_intervals("in", start_intervals, workflows);
+fn get_accept_ratings_combinations(tree: &DecisionTree) -> aoc::Result<u128> {
+    let accept_intervals = collect_accept_intervals(tree.roots["in"], tree.full_intervals(), tree);
 
     let ratings_cartesian_product = |intervals: &RatingsIntervals| {
         intervals
             .iter()
-            .map(|(start, end)| end - start + 1)
-            .product::<u64>()
+            .map(|(start, end)| (end - start + 1) as u128)
+            .try_fold(1, u128::checked_mul)
     };
-    accept_intervals.iter().map(ratings_cartesian_product).sum()
+    accept_intervals
+        .iter()
+        .map(ratings_cartesian_product)
+        .try_fold(0u128, |sum, product| sum.checked_add(product?))
+        .context("ratings combinations overflow")
 }
 
+/// Expects workflows that passed `check_workflows`, so it doesn't recurse forever.
 fn collect_accept_intervals(
-    workflow_id: &str,
+    node: usize,
     intervals: RatingsIntervals,
-    workflows: &HashMap<&str, Workflow>,
+    tree: &DecisionTree,
 ) -> Vec<RatingsIntervals> {
-    let Some(workflow) = workflows.get(workflow_id) else {
-        return vec![];
+    match tree.nodes[node] {
+        Node::Test {
+            category,
+            threshold,
+            below,
+            above,
+        } => {
+            let (below_intervals, above_intervals) =
+                split_intervals(intervals, category, threshold);
+            let mut accept_intervals = vec![];
+            if let Some(below_intervals) = below_intervals {
+                accept_intervals.extend(collect_accept_intervals(below, below_intervals, tree));
+            }
+            if let Some(above_intervals) = above_intervals {
+                accept_intervals.extend(collect_accept_intervals(above, above_intervals, tree));
+            }
+            accept_intervals
+        }
+        Node::Output(Output::Accept) => vec![intervals],
+        Node::Output(Output::Reject) => vec![],
+        Node::Output(Output::Workflow(id)) => {
+            collect_accept_intervals(tree.roots[id], intervals, tree)
+        }
+    }
+}
+
+/// Splits the intervals into the ratings below the threshold for the given category and the ones
+/// above it. Either side is `None` if it has no ratings.
+fn split_intervals(
+    intervals: RatingsIntervals,
+    category: usize,
+    threshold: u64,
+) -> (Option<RatingsIntervals>, Option<RatingsIntervals>) {
+    let (start, end) = intervals[category];
+    let below = (start < threshold).then(|| {
+        let mut below = intervals.clone();
+        below[category] = (start, end.min(threshold - 1));
+        below
+    });
+    let above = (end >= threshold).then(|| {
+        let mut above = intervals;
+        above[category] = (start.max(threshold), end);
+        above
+    });
+    (below, above)
+}
+
+/// Finds a set of workflows that accepts the same parts, but with no more rules than necessary, and
+/// writes them in the puzzle's format, using only `<` and `>` comparisons. Not necessarily the
+/// smallest possible set of workflows though; that would be a much harder problem.
+///
+/// The accepted ratings intervals are boxes on the ratings space. Boxes that line up are merged
+/// first, and then the space gets split by one rating threshold at a time, until each side is
+/// either fully accepted or fully rejected.
+fn simplify_workflows(tree: &DecisionTree) -> String {
+    let accept_intervals = collect_accept_intervals(tree.roots["in"], tree.full_intervals(), tree);
+    let boxes = merge_adjacent_boxes(accept_intervals);
+    let decision = build_decision(tree.full_intervals(), boxes);
+
+    let mut workflows = vec![];
+    add_decision_workflow(&decision, &tree.categories, &mut workflows);
+    let mut output = String::new();
+    for (i, rules) in workflows.iter().enumerate() {
+        output += &format!("{}{{{}}}\n", simplified_workflow_id(i), rules.join(","));
+    }
+    output
+}
+
+/// A decision tree over ratings, where each split tests a single rating against a threshold, like
+/// the `Node::Test` ones.
+enum Decision {
+    Accept,
+    Reject,
+    Split {
+        category: usize,
+        threshold: u64,
+        below: Box<Decision>,
+        above: Box<Decision>,
+    },
+}
+
+/// Merges boxes that are next to each other on one category and span the same intervals on all the
+/// other ones, until there's nothing left to merge.
+fn merge_adjacent_boxes(mut boxes: Vec<RatingsIntervals>) -> Vec<RatingsIntervals> {
+    let categories_count = boxes.first().map_or(0, |b| b.len());
+    loop {
+        let boxes_count = boxes.len();
+        for category in 0..categories_count {
+            // Group the boxes by their intervals on all the other categories.
+            let mut groups: BTreeMap<RatingsIntervals, Vec<(u64, u64)>> = BTreeMap::new();
+            for mut b in boxes {
+                let interval = b[category];
+                b[category] = (0, 0);
+                groups.entry(b).or_default().push(interval);
+            }
+            boxes = vec![];
+            for (group, mut intervals) in groups {
+                intervals.sort_unstable();
+                let mut merged_intervals: Vec<(u64, u64)> = vec![];
+                for (start, end) in intervals {
+                    match merged_intervals.last_mut() {
+                        Some(last) if last.1 + 1 == start => last.1 = end,
+                        _ => merged_intervals.push((start, end)),
+                    }
+                }
+                for interval in merged_intervals {
+                    let mut b = group.clone();
+                    b[category] = interval;
+                    boxes.push(b);
+                }
+            }
+        }
+        if boxes.len() == boxes_count {
+            return boxes;
+        }
+    }
+}
+
+/// Builds a decision tree that accepts the ratings within the given boxes, which must be disjoint
+/// and within the region.
+fn build_decision(region: RatingsIntervals, boxes: Vec<RatingsIntervals>) -> Decision {
+    if boxes.is_empty() {
+        return Decision::Reject;
+    }
+    let volume = |intervals: &RatingsIntervals| {
+        intervals
+            .iter()
+            .map(|(start, end)| (end - start + 1) as u128)
+            .product::<u128>()
+    };
+    if boxes.iter().map(volume).sum::<u128>() == volume(&region) {
+        return Decision::Accept;
+    }
+
+    let (category, threshold) = choose_split(&region, &boxes);
+    let (below_boxes, above_boxes): (Vec<_>, Vec<_>) = boxes
+        .into_iter()
+        .map(|b| split_intervals(b, category, threshold))
+        .unzip();
+    let (Some(below_region), Some(above_region)) = split_intervals(region, category, threshold)
+    else {
+        unreachable!("split thresholds are always within the region");
     };
-    let mut intervals = intervals;
-    let mut accept_intervals = vec![];
-    for rule in workflow.rules.iter() {
-        let matching_intervals = rule.condition.apply_to_intervals(intervals);
-        match &rule.output {
-            Output::Accept => accept_intervals.push(matching_intervals),
-            Output::Reject => {}
-            Output::Workflow(id) => {
-                accept_intervals.extend(collect_accept_intervals(id, matching_intervals, workflows))
+    let below = build_decision(below_region, below_boxes.into_iter().flatten().collect());
+    let above = build_decision(above_region, above_boxes.into_iter().flatten().collect());
+    match (below, above) {
+        (Decision::Accept, Decision::Accept) => Decision::Accept,
+        (Decision::Reject, Decision::Reject) => Decision::Reject,
+        (below, above) => Decision::Split {
+            category,
+            threshold,
+            below: Box::new(below),
+            above: Box::new(above),
+        },
+    }
+}
+
+/// Picks the threshold among the box edges that cuts the fewest boxes in two, breaking ties by how
+/// evenly it splits them, to keep the decision tree small.
+fn choose_split(region: &RatingsIntervals, boxes: &[RatingsIntervals]) -> (usize, u64) {
+    let mut best_split: Option<((usize, usize), usize, u64)> = None;
+    for (category, &(region_start, region_end)) in region.iter().enumerate() {
+        let starts = boxes
+            .iter()
+            .map(|b| b[category].0)
+            .sorted_unstable()
+            .collect_vec();
+        let ends = boxes
+            .iter()
+            .map(|b| b[category].1)
+            .sorted_unstable()
+            .collect_vec();
+        let thresholds = starts.iter().copied().filter(|&start| start > region_start);
+        let thresholds = thresholds.chain(
+            ends.iter()
+                .filter(|&&end| end < region_end)
+                .map(|end| end + 1),
+        );
+        for threshold in thresholds {
+            let below_count = ends.partition_point(|&end| end < threshold);
+            let above_count = boxes.len() - starts.partition_point(|&start| start < threshold);
+            let cut_count = boxes.len() - below_count - above_count;
+            let cost = (cut_count, below_count.max(above_count) + cut_count);
+            if best_split.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                best_split = Some((cost, category, threshold));
             }
         }
-        // Continue with the rest of the rules considering this rule's condition not matched.
-        intervals = rule.condition.negated().apply_to_intervals(intervals);
     }
-    match &workflow.fallback {
-        Output::Accept => accept_intervals.push(intervals),
-        Output::Reject => {}
-        Output::Workflow(id) => {
-            accept_intervals.extend(collect_accept_intervals(id, intervals, workflows))
+    // The boxes don't cover the whole region, so some box edge must be inside it.
+    let (_, category, threshold) = best_split.expect("no threshold to split the region");
+    (category, threshold)
+}
+
+/// Adds a workflow for the decision tree, and for any subtrees it needs, and returns its index.
+///
+/// Each workflow is a chain of rules: a split where one side is a final decision becomes a rule
+/// with that output, and the workflow goes on with the other side. Splits where both sides need
+/// more decisions go to a new workflow for one of them.
+fn add_decision_workflow(
+    decision: &Decision,
+    categories: &[&str],
+    workflows: &mut Vec<Vec<String>>,
+) -> usize {
+    let index = workflows.len();
+    workflows.push(vec![]);
+    let output = |decision: &Decision| match decision {
+        Decision::Accept => Some("A"),
+        Decision::Reject => Some("R"),
+        Decision::Split { .. } => None,
+    };
+
+    let mut rules = vec![];
+    let mut decision = decision;
+    let fallback = loop {
+        let Decision::Split {
+            category,
+            threshold,
+            below,
+            above,
+        } = decision
+        else {
+            break output(decision).unwrap_or_default().to_string();
+        };
+        let category = categories[*category];
+        if let Some(below_output) = output(below) {
+            rules.push((format!("{category}<{threshold}"), below_output.to_string()));
+            decision = above;
+        } else if let Some(above_output) = output(above) {
+            rules.push((
+                format!("{category}>{}", threshold - 1),
+                above_output.to_string(),
+            ));
+            decision = below;
+        } else {
+            let below_index = add_decision_workflow(below, categories, workflows);
+            let below_id = simplified_workflow_id(below_index);
+            rules.push((format!("{category}<{threshold}"), below_id));
+            decision = above;
+        }
+    };
+    // Trailing rules that give the same output as the fallback don't make any difference.
+    while rules.last().is_some_and(|(_, output)| *output == fallback) {
+        rules.pop();
+    }
+
+    workflows[index] = rules
+        .into_iter()
+        .map(|(condition, output)| format!("{condition}:{output}"))
+        .chain([fallback])
+        .collect();
+    index
+}
+
+fn simplified_workflow_id(index: usize) -> String {
+    if index == 0 {
+        "in".to_string()
+    } else {
+        format!("w{index}")
+    }
+}
+
+/// Checks that two sets of workflows accept the same parts, or returns an error with a part that
+/// only one of them accepts.
+fn check_equivalent(tree: &DecisionTree, other_tree: &DecisionTree) -> aoc::Result<()> {
+    let mut categories = tree.categories.clone();
+    for category in &other_tree.categories {
+        if !categories.contains(category) {
+            categories.push(category);
+        }
+    }
+    let boxes = accept_boxes(tree, &categories);
+    let other_boxes = accept_boxes(other_tree, &categories);
+    let differences = [
+        (
+            &boxes,
+            &other_boxes,
+            "accepted by these workflows but not by the other ones",
+        ),
+        (
+            &other_boxes,
+            &boxes,
+            "accepted by the other workflows but not by these ones",
+        ),
+    ];
+    for (boxes, other_boxes, difference) in differences {
+        if let Some(ratings) = find_uncovered_ratings(boxes, other_boxes) {
+            let part = categories
+                .iter()
+                .zip(ratings)
+                .map(|(category, rating)| format!("{category}={rating}"))
+                .join(",");
+            bail!("part {{{part}}} is {difference}");
         }
     }
+    Ok(())
+}
+
+/// The accepted ratings intervals of the workflows, with the intervals of the given categories,
+/// which must include all of the tree's ones.
+fn accept_boxes(tree: &DecisionTree, categories: &[&str]) -> Vec<RatingsIntervals> {
+    let accept_intervals = collect_accept_intervals(tree.roots["in"], tree.full_intervals(), tree);
     accept_intervals
+        .into_iter()
+        .map(|intervals| {
+            categories
+                .iter()
+                .map(
+                    |category| match tree.categories.iter().position(|c| c == category) {
+                        Some(index) => intervals[index],
+                        None => (1, 4000),
+                    },
+                )
+                .collect()
+        })
+        .collect()
 }
 
-impl Workflow {
-    fn parse(s: &str) -> aoc::Result<Workflow> {
-        let rules = s.split(',').collect_vec();
-        let [rules @ .., fallback] = rules.as_slice() else {
-            bail!("expected at least one fallback rule")
+/// Finds some ratings that are in some of the boxes but not in any of the other ones.
+fn find_uncovered_ratings(
+    boxes: &[RatingsIntervals],
+    other_boxes: &[RatingsIntervals],
+) -> Option<Vec<u64>> {
+    boxes.iter().find_map(|b| {
+        let mut uncovered = vec![b.clone()];
+        for other in other_boxes {
+            uncovered = uncovered
+                .iter()
+                .flat_map(|uncovered_box| subtract_box(uncovered_box, other))
+                .collect();
+        }
+        let uncovered_box = uncovered.first()?;
+        Some(uncovered_box.iter().map(|&(start, _)| start).collect())
+    })
+}
+
+/// Splits the part of box `a` that's not in box `b` into disjoint boxes.
+fn subtract_box(a: &RatingsIntervals, b: &RatingsIntervals) -> Vec<RatingsIntervals> {
+    let overlap = a
+        .iter()
+        .zip(b)
+        .all(|(&(a_start, a_end), &(b_start, b_end))| a_start <= b_end && b_start <= a_end);
+    if !overlap {
+        return vec![a.clone()];
+    }
+    // Slice off the parts of `a` that stick out of `b` one category at a time.
+    let mut pieces = vec![];
+    let mut rest = a.clone();
+    for (category, &(b_start, b_end)) in b.iter().enumerate() {
+        let (start, end) = rest[category];
+        if start < b_start {
+            let mut piece = rest.clone();
+            piece[category] = (start, b_start - 1);
+            pieces.push(piece);
+        }
+        if end > b_end {
+            let mut piece = rest.clone();
+            piece[category] = (b_end + 1, end);
+            pieces.push(piece);
+        }
+        rest[category] = (start.max(b_start), end.min(b_end));
+    }
+    pieces
+}
+
+/// Checks the workflows for problems that would make processing parts go wrong: a missing `in`
+/// workflow, references to undefined workflows, and cycles, which would make processing loop
+/// forever. Returns warnings for things that are harmless but most likely mistakes: workflows that
+/// can't be reached from `in`, and rules that can never match because the preceding rules of their
+/// workflow already catch all the ratings they would match.
+fn check_workflows(workflows: &Workflows, tree: &DecisionTree) -> aoc::Result<Vec<String>> {
+    ensure!(tree.roots.contains_key("in"), "workflow 'in' not found");
+    for (id, workflow) in workflows {
+        for next in workflow.next_workflows() {
+            ensure!(
+                tree.roots.contains_key(next),
+                "workflow '{id}' refers to undefined workflow '{next}'"
+            );
+        }
+    }
+    if let Some(cycle) = find_workflows_cycle(workflows) {
+        bail!("workflows form a cycle: {}", cycle.join(" -> "));
+    }
+
+    let mut warnings = vec![];
+    let workflows_by_id: HashMap<_, _> = workflows.iter().map(|(id, w)| (*id, w)).collect();
+    let mut reachable = HashSet::from(["in"]);
+    let mut pending = vec!["in"];
+    while let Some(id) = pending.pop() {
+        for next in workflows_by_id[id].next_workflows() {
+            if reachable.insert(next) {
+                pending.push(next);
+            }
+        }
+    }
+    for (id, _) in workflows {
+        if !reachable.contains(id) {
+            warnings.push(format!("workflow '{id}' can't be reached from 'in'"));
+        }
+    }
+
+    for (id, workflow) in workflows {
+        let mut reached_outputs = HashSet::new();
+        tree.collect_reached_outputs(tree.roots[id], tree.full_intervals(), &mut reached_outputs);
+        for (i, node) in tree.outputs[id].iter().enumerate() {
+            if reached_outputs.contains(node) {
+                continue;
+            }
+            if i < workflow.rules.len() {
+                warnings.push(format!(
+                    "rule #{} of workflow '{id}' can never match",
+                    i + 1
+                ));
+            } else {
+                warnings.push(format!("fallback of workflow '{id}' can never be reached"));
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+/// Finds a cycle on the graph of workflows going to other workflows with a depth-first search.
+/// Returns the IDs of the workflows on the cycle, starting and ending with the same one.
+fn find_workflows_cycle<'a>(workflows: &Workflows<'a>) -> Option<Vec<&'a str>> {
+    fn visit<'a>(
+        id: &'a str,
+        workflows_by_id: &HashMap<&'a str, &Workflow<'a>>,
+        visited: &mut HashSet<&'a str>,
+        path: &mut Vec<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        if let Some(cycle_start) = path.iter().position(|&p| p == id) {
+            let mut cycle = path[cycle_start..].to_vec();
+            cycle.push(id);
+            return Some(cycle);
+        }
+        if !visited.insert(id) {
+            return None;
+        }
+        path.push(id);
+        for next in workflows_by_id[id].next_workflows() {
+            if let Some(cycle) = visit(next, workflows_by_id, visited, path) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        None
+    }
+
+    let workflows_by_id: HashMap<_, _> = workflows.iter().map(|(id, w)| (*id, w)).collect();
+    let mut visited = HashSet::new();
+    workflows
+        .iter()
+        .find_map(|(id, _)| visit(id, &workflows_by_id, &mut visited, &mut vec![]))
+}
+
+impl<'a> DecisionTree<'a> {
+    fn compile(workflows: &[(&'a str, Workflow<'a>)]) -> aoc::Result<DecisionTree<'a>> {
+        let mut tree = DecisionTree {
+            nodes: vec![],
+            roots: HashMap::new(),
+            outputs: HashMap::new(),
+            categories: vec![],
         };
-        let rules = rules.iter().map(|s| Rule::parse(s)).try_collect()?;
-        let fallback = Output::parse(fallback);
-        Ok(Workflow { rules, fallback })
+        for (id, workflow) in workflows {
+            // Compile the rules backwards, so each rule's condition can go to the rest of the rules
+            // when it doesn't match.
+            let mut node = tree.add_node(Node::Output(workflow.fallback));
+            let mut outputs = vec![node];
+            for rule in workflow.rules.iter().rev() {
+                let matched = tree.add_node(Node::Output(rule.output));
+                outputs.push(matched);
+                node = tree.compile_expr(&rule.condition, matched, node);
+            }
+            outputs.reverse();
+            let duplicate = tree.roots.insert(id, node).is_some();
+            ensure!(!duplicate, "duplicate workflow '{id}'");
+            tree.outputs.insert(id, outputs);
+        }
+        Ok(tree)
+    }
+
+    fn add_node(&mut self, node: Node<'a>) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// The intervals of all possible ratings.
+    fn full_intervals(&self) -> RatingsIntervals {
+        vec![(1, 4000); self.categories.len()]
+    }
+
+    /// Collects the output nodes that some ratings within the intervals reach from the given node,
+    /// without going into other workflows.
+    fn collect_reached_outputs(
+        &self,
+        node: usize,
+        intervals: RatingsIntervals,
+        reached_outputs: &mut HashSet<usize>,
+    ) {
+        match self.nodes[node] {
+            Node::Test {
+                category,
+                threshold,
+                below,
+                above,
+            } => {
+                let (below_intervals, above_intervals) =
+                    split_intervals(intervals, category, threshold);
+                if let Some(below_intervals) = below_intervals {
+                    self.collect_reached_outputs(below, below_intervals, reached_outputs);
+                }
+                if let Some(above_intervals) = above_intervals {
+                    self.collect_reached_outputs(above, above_intervals, reached_outputs);
+                }
+            }
+            Node::Output(_) => {
+                reached_outputs.insert(node);
+            }
+        }
+    }
+
+    /// Returns the index of the given category, adding it if it's new.
+    fn category_index(&mut self, category: &'a str) -> usize {
+        match self.categories.iter().position(|&c| c == category) {
+            Some(index) => index,
+            None => {
+                self.categories.push(category);
+                self.categories.len() - 1
+            }
+        }
+    }
+
+    /// Compiles an expression into test nodes that go to the `then` node if the expression holds,
+    /// or to the `otherwise` node if it doesn't.
+    fn compile_expr(&mut self, expr: &Expr<'a>, then: usize, otherwise: usize) -> usize {
+        match expr {
+            &Expr::Compare {
+                category,
+                op,
+                value,
+            } => {
+                let category = self.category_index(category);
+                self.compile_comparison(category, op, value, then, otherwise)
+            }
+            Expr::Not(expr) => self.compile_expr(expr, otherwise, then),
+            Expr::And(lhs, rhs) => {
+                let rhs = self.compile_expr(rhs, then, otherwise);
+                self.compile_expr(lhs, rhs, otherwise)
+            }
+            Expr::Or(lhs, rhs) => {
+                let rhs = self.compile_expr(rhs, then, otherwise);
+                self.compile_expr(lhs, then, rhs)
+            }
+        }
     }
 
-    fn process(&self, part: &Part) -> &Output {
-        self.rules
+    fn compile_comparison(
+        &mut self,
+        category: usize,
+        op: Operator,
+        value: u64,
+        then: usize,
+        otherwise: usize,
+    ) -> usize {
+        let mut test = |threshold, below, above| {
+            self.add_node(Node::Test {
+                category,
+                threshold,
+                below,
+                above,
+            })
+        };
+        // A rating <= value is a rating < value + 1, unless value + 1 overflows, in which case
+        // it always holds.
+        match (op, value.checked_add(1)) {
+            (Operator::Lt, _) => test(value, then, otherwise),
+            (Operator::Ge, _) => test(value, otherwise, then),
+            (Operator::Le, Some(next)) => test(next, then, otherwise),
+            (Operator::Le, None) => then,
+            (Operator::Gt, Some(next)) => test(next, otherwise, then),
+            (Operator::Gt, None) => otherwise,
+            (Operator::Eq, _) => {
+                let not_below =
+                    self.compile_comparison(category, Operator::Le, value, then, otherwise);
+                self.compile_comparison(category, Operator::Ge, value, not_below, otherwise)
+            }
+            (Operator::Ne, _) => {
+                self.compile_comparison(category, Operator::Eq, value, otherwise, then)
+            }
+        }
+    }
+
+    /// Puts the ratings of a part in category index order. All categories must be rated.
+    fn part_ratings(&self, part: &Part) -> aoc::Result<Ratings> {
+        self.categories
             .iter()
-            .find_map(|r| r.process(part))
-            .unwrap_or(&self.fallback)
+            .map(|&category| {
+                let rating = part.iter().find(|&&(c, _)| c == category);
+                let (_, rating) = rating.with_context(|| format!("no rating for '{category}'"))?;
+                Ok(*rating)
+            })
+            .collect()
     }
 }
 
-impl Rule {
-    fn parse(s: &str) -> aoc::Result<Rule> {
-        let (condition, output) = s.split_once(':').context("invalid rule")?;
-        let condition = Condition::parse(condition)?;
-        let output = Output::parse(output);
-        Ok(Rule { condition, output })
+/// A recursive descent parser for rule conditions, with this grammar:
+///
+/// ```text
+/// or         = and ("||" and)*
+/// and        = unary ("&&" unary)*
+/// unary      = "!" unary | "(" or ")" | comparison
+/// comparison = category ("<" | ">" | "<=" | ">=" | "==" | "!=") number
+/// ```
+struct ExprParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn parse(s: &'a str) -> aoc::Result<Expr<'a>> {
+        let mut parser = ExprParser { s, pos: 0 };
+        let expr = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < s.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(expr)
     }
 
-    fn process(&self, part: &Part) -> Option<&Output> {
-        if self.condition.matches(part) {
-            Some(&self.output)
-        } else {
-            None
+    fn parse_or(&mut self) -> aoc::Result<Expr<'a>> {
+        let mut expr = self.parse_and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
         }
+        Ok(expr)
     }
-}
 
-impl Condition {
-    fn parse(s: &str) -> aoc::Result<Condition> {
-        let category = &s[0..1];
-        let op = &s[1..2];
-        let value = &s[2..];
-        let category_index = match category {
-            "x" => 0,
-            "m" => 1,
-            "a" => 2,
-            "s" => 3,
-            _ => bail!("invalid category '{category}'"),
-        };
-        let op = Operator::parse(op)?;
-        let value = value.parse()?;
-        Ok(Condition {
-            category_index,
+    fn parse_and(&mut self) -> aoc::Result<Expr<'a>> {
+        let mut expr = self.parse_unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> aoc::Result<Expr<'a>> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected ')'"));
+            }
+            return Ok(expr);
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> aoc::Result<Expr<'a>> {
+        let category = self.take_while(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+        if category.is_empty() {
+            return Err(self.error("expected a category name"));
+        }
+        // Two-character operators first, so "<=" doesn't get parsed as "<".
+        let operators = [
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("==", Operator::Eq),
+            ("!=", Operator::Ne),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ];
+        let op = operators
+            .into_iter()
+            .find_map(|(token, op)| self.eat(token).then_some(op))
+            .ok_or_else(|| self.error("expected a comparison operator"))?;
+        let value = self.take_while(|ch| ch.is_ascii_digit());
+        let value = value.parse().map_err(|_| self.error("expected a number"))?;
+        Ok(Expr::Compare {
+            category,
             op,
             value,
         })
     }
 
-    fn matches(&self, part: &Part) -> bool {
-        let part_value = part[self.category_index];
-        self.op.apply(part_value, self.value)
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
     }
 
-    fn apply_to_intervals(&self, intervals: RatingsIntervals) -> RatingsIntervals {
-        let mut intervals = intervals;
-        let (start, end) = &mut intervals[self.category_index];
-        match self.op {
-            Operator::Gt => *start = (self.value + 1).max(*start),
-            Operator::Lt => *end = (self.value - 1).min(*end),
+    /// Consumes the given token if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.s[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
         }
-        intervals
     }
 
-    fn negated(&self) -> Condition {
-        let (op, value) = match self.op {
-            Operator::Gt => (Operator::Lt, self.value + 1),
-            Operator::Lt => (Operator::Gt, self.value - 1),
-        };
-        Condition { op, value, ..*self }
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.s[self.pos..];
+        let len = rest.find(|ch| !predicate(ch)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!("{message} at column {} of '{}'", self.pos + 1, self.s)
     }
 }
 
-impl Operator {
-    fn parse(s: &str) -> aoc::Result<Operator> {
-        Ok(match s {
-            "<" => Operator::Lt,
-            ">" => Operator::Gt,
-            _ => bail!("invalid operator '{s}'"),
-        })
+impl<'a> Workflow<'a> {
+    /// The IDs of the workflows this one can send parts to.
+    fn next_workflows(&self) -> impl Iterator<Item = &'a str> + '_ {
+        let outputs = self.rules.iter().map(|rule| rule.output);
+        outputs
+            .chain([self.fallback])
+            .filter_map(|output| match output {
+                Output::Workflow(id) => Some(id),
+                _ => None,
+            })
     }
 
-    fn apply(&self, lhs: u64, rhs: u64) -> bool {
-        match self {
-            Operator::Gt => lhs > rhs,
-            Operator::Lt => lhs < rhs,
-        }
+    fn parse(s: &'a str) -> aoc::Result<Workflow<'a>> {
+        let rules = s.split(',').collect_vec();
+        let [rules @ .., fallback] = rules.as_slice() else {
+            bail!("expected at least one fallback rule")
+        };
+        let rules = rules.iter().map(|s| Rule::parse(s)).try_collect()?;
+        let fallback = Output::parse(fallback)?;
+        Ok(Workflow { rules, fallback })
     }
 }
 
-impl Output {
-    fn parse(s: &str) -> Output {
-        match s {
+impl<'a> Rule<'a> {
+    fn parse(s: &'a str) -> aoc::Result<Rule<'a>> {
+        let (condition, output) = s
+            .rsplit_once(':')
+            .with_context(|| format!("invalid rule '{s}': expected <condition>:<output>"))?;
+        let condition =
+            ExprParser::parse(condition).with_context(|| format!("invalid rule '{s}'"))?;
+        let output = Output::parse(output)?;
+        Ok(Rule { condition, output })
+    }
+}
+
+impl<'a> Output<'a> {
+    fn parse(s: &'a str) -> aoc::Result<Output<'a>> {
+        let output = match s {
             "A" => Output::Accept,
             "R" => Output::Reject,
-            _ => Output::Workflow(s.to_string()),
-        }
+            _ => Output::Workflow(parse_name(s)?),
+        };
+        Ok(output)
     }
 }
 
-fn parse_input(input: &str) -> aoc::Result<(HashMap<&str, Workflow>, Vec<Part>)> {
-    let (workflows_input, parts_input) = input.split_once("\n\n").context("invalid input")?;
-    let workflows = workflows_input.lines().map(parse_workflow).try_collect()?;
-    let parts = parts_input.lines().map(parse_part).try_collect()?;
-    Ok((workflows, parts))
+/// Parses, compiles and checks the workflows, printing any warnings.
+fn load_workflows(input: &str) -> aoc::Result<DecisionTree<'_>> {
+    let workflows: Workflows = input.lines().map(parse_workflow).try_collect()?;
+    let tree = DecisionTree::compile(&workflows)?;
+    for warning in check_workflows(&workflows, &tree)? {
+        eprintln!("warning: {warning}");
+    }
+    Ok(tree)
 }
 
-fn parse_workflow(s: &str) -> aoc::Result<(&str, Workflow)> {
+fn parse_workflow(s: &str) -> aoc::Result<(&str, Workflow<'_>)> {
     let (id, workflow) = s
-        .split(['{', '}'])
-        .filter(|s| !s.is_empty())
-        .collect_tuple()
-        .context("invalid workflow")?;
-    Ok((id, Workflow::parse(workflow)?))
+        .strip_suffix('}')
+        .and_then(|s| s.split_once('{'))
+        .with_context(|| format!("invalid workflow '{s}'"))?;
+    let workflow = Workflow::parse(workflow).with_context(|| format!("invalid workflow '{id}'"))?;
+    Ok((parse_name(id)?, workflow))
+}
+
+fn parse_part(s: &str) -> aoc::Result<Part<'_>> {
+    let ratings = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .with_context(|| format!("invalid part '{s}'"))?;
+    ratings
+        .split(',')
+        .map(|rating| {
+            let (category, value) = rating
+                .split_once('=')
+                .with_context(|| format!("invalid rating '{rating}'"))?;
+            Ok((parse_name(category)?, value.parse()?))
+        })
+        .try_collect()
 }
 
-fn parse_part(s: &str) -> aoc::Result<Part> {
-    let numbers = aoc::parse_numbers(s)?;
-    Ok(numbers[..].try_into()?)
+/// Checks that a workflow or category name is made of letters, digits and underscores.
+fn parse_name(s: &str) -> aoc::Result<&str> {
+    let valid = !s.is_empty() && s.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    ensure!(valid, "invalid name '{s}'");
+    Ok(s)
 }
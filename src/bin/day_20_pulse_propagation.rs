@@ -1,140 +1,510 @@
-use anyhow::{bail, Context};
+use anyhow::{bail, ensure, Context};
+use aoc::congruence;
 use itertools::Itertools;
-use std::collections::{HashMap, VecDeque};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    env, fmt,
+};
 
+/// Note: Run this daily solution with TRACE=<n> env var to print all the pulses sent on the first
+/// n button presses, in the same format as the puzzle description.
+///
+/// Run with PRESSES=<n> to count the pulses of n button presses on part 1 instead of 1000, and
+/// with TARGET=<module> to count the button presses until some other module than "rx" gets a low
+/// pulse on part 2. An empty TARGET skips part 2, as the samples have no such module.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
-    let modules_data = parse_modules(&input)?;
-    let (mut modules, outputs): (HashMap<_, _>, HashMap<_, _>) = modules_data
-        .into_iter()
-        .map(|(name, module, outputs)| ((name, module), (name, outputs)))
-        .unzip();
-    let is_real_input = input.contains("-> rx");
-
-    // Wire conjunction modules with their inputs.
-    for (name, outs) in outputs.iter() {
-        for out_name in outs.iter() {
-            if let Some(Module::Conj { mem }) = modules.get_mut(*out_name) {
-                mem.insert(name, LOW);
+    let mut network = PulseNetwork::parse(&input)?;
+    let trace_presses = parse_number_var("TRACE")?.unwrap_or(0);
+    let presses = parse_number_var("PRESSES")?.unwrap_or(1000);
+    let target = env::var("TARGET").unwrap_or("rx".to_string());
+
+    let initial_state = network.snapshot();
+    let mut low_count = 0;
+    let mut high_count = 0;
+    for button_push in 1..=presses {
+        let stats = if button_push <= trace_presses {
+            let stats = network.push_button_with(|pulse| println!("{pulse}"));
+            println!();
+            stats
+        } else {
+            network.push_button()
+        };
+        low_count += stats.low_pulses;
+        high_count += stats.high_pulses;
+    }
+    let part_1_ans = low_count * high_count;
+    if target.is_empty() {
+        println!("{part_1_ans}");
+        return Ok(());
+    }
+
+    network.restore(&initial_state);
+    let part_2_ans = network.count_presses_until_low_pulse(&target)?;
+
+    println!("{part_1_ans} {part_2_ans}");
+    Ok(())
+}
+
+const LOW: bool = false;
+const HIGH: bool = true;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ModuleKind {
+    /// The button isn't declared on the input, but it's convenient to have it as a module that
+    /// sends pulses to the broadcaster.
+    Button,
+    Broadcaster,
+    FlipFlop,
+    Conj,
+    /// Modules that only appear as outputs, like "rx". They receive pulses but never send any.
+    Untyped,
+}
+
+/// A pulse sent from one module to another, displayed like `a -high-> b`.
+#[derive(Clone, Copy)]
+struct Pulse<'a> {
+    src: &'a str,
+    dst: &'a str,
+    level: bool,
+    dst_index: usize,
+    /// The input slot of the destination module the pulse arrives at.
+    dst_slot: usize,
+    /// How many pulses it took to get here since the button was pushed, with the button's pulse
+    /// having depth 0. Pulses get delivered in depth order.
+    depth: usize,
+}
+
+#[derive(Default, Clone, Copy)]
+struct PressStats {
+    low_pulses: u64,
+    high_pulses: u64,
+}
+
+/// The modules of the network, numbered by their index, wired together, and with their current
+/// state.
+struct PulseNetwork<'a> {
+    names: Vec<&'a str>,
+    kinds: Vec<ModuleKind>,
+    /// The output modules of each module, along with the input slot on the output module.
+    outputs: Vec<Vec<(usize, usize)>>,
+    /// The input modules of each module, in slot order.
+    inputs: Vec<Vec<usize>>,
+    state: NetworkState,
+    /// Pulses waiting to be delivered: source, destination, destination input slot, and level.
+    /// Kept here only to reuse its allocation between button presses.
+    pending_pulses: VecDeque<(usize, usize, usize, bool)>,
+}
+
+/// The mutable state of all modules of a network, which can be snapshotted and restored.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct NetworkState {
+    /// Whether each module is on. Only used for flip-flops.
+    on: Vec<bool>,
+    /// The last pulse level received on each input slot of each module. Only used for
+    /// conjunctions.
+    memory: Vec<Vec<bool>>,
+}
+
+const BUTTON: usize = 0;
+
+impl<'a> PulseNetwork<'a> {
+    fn parse(input: &'a str) -> aoc::Result<Self> {
+        let mut names = vec!["button"];
+        let mut kinds = vec![ModuleKind::Button];
+        let mut output_names = vec![vec!["broadcaster"]];
+        for line in input.lines() {
+            let (name, kind, outputs) =
+                parse_module(line).with_context(|| format!("invalid line '{line}'"))?;
+            ensure!(!names.contains(&name), "duplicate module '{name}'");
+            names.push(name);
+            kinds.push(kind);
+            output_names.push(outputs);
+        }
+        ensure!(
+            names.contains(&"broadcaster"),
+            "broadcaster module not found"
+        );
+
+        let mut index: HashMap<_, _> = names.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+        let mut outputs = vec![];
+        let mut inputs = vec![vec![]; names.len()];
+        for (module, outs) in output_names.into_iter().enumerate() {
+            let mut module_outputs = vec![];
+            for out_name in outs {
+                let out = *index.entry(out_name).or_insert_with(|| {
+                    names.push(out_name);
+                    kinds.push(ModuleKind::Untyped);
+                    inputs.push(vec![]);
+                    names.len() - 1
+                });
+                module_outputs.push((out, inputs[out].len()));
+                inputs[out].push(module);
             }
+            outputs.push(module_outputs);
         }
+        outputs.resize(names.len(), vec![]);
+
+        let state = NetworkState {
+            on: vec![false; names.len()],
+            memory: inputs.iter().map(|ins| vec![LOW; ins.len()]).collect(),
+        };
+        Ok(PulseNetwork {
+            names,
+            kinds,
+            outputs,
+            inputs,
+            state,
+            pending_pulses: VecDeque::new(),
+        })
     }
 
-    let mut low_count = 0;
-    let mut high_count = 0;
-    let mut conj_first_low_emits = HashMap::new();
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|&n| n == name)
+    }
+
+    fn snapshot(&self) -> NetworkState {
+        self.state.clone()
+    }
+
+    fn restore(&mut self, state: &NetworkState) {
+        self.state.clone_from(state);
+    }
+
+    fn push_button(&mut self) -> PressStats {
+        self.push_button_with(|_| {})
+    }
 
-    for button_push in 1.. {
-        let mut pulses = VecDeque::from([("button", "broadcaster", LOW)]);
+    /// Pushes the button and delivers all pulses until the network settles down, calling
+    /// `on_pulse` with each pulse in the order they get delivered.
+    fn push_button_with(&mut self, mut on_pulse: impl FnMut(Pulse<'a>)) -> PressStats {
+        let mut stats = PressStats::default();
+        let (broadcaster, slot) = self.outputs[BUTTON][0];
+        self.pending_pulses
+            .push_back((BUTTON, broadcaster, slot, LOW));
 
-        while let Some(pulse) = pulses.pop_front() {
-            let (src, dst, value) = pulse;
-            high_count += value as u64;
-            low_count += !value as u64;
+        let mut depth = 0;
+        let mut depth_pulses_left = self.pending_pulses.len();
+        while let Some((src, dst, slot, level)) = self.pending_pulses.pop_front() {
+            // All pending pulses after the ones of the current depth were sent by those, so they're
+            // one level deeper.
+            if depth_pulses_left == 0 {
+                depth += 1;
+                depth_pulses_left = self.pending_pulses.len() + 1;
+            }
+            depth_pulses_left -= 1;
+            if level == HIGH {
+                stats.high_pulses += 1;
+            } else {
+                stats.low_pulses += 1;
+            }
+            on_pulse(Pulse {
+                src: self.names[src],
+                dst: self.names[dst],
+                level,
+                dst_index: dst,
+                dst_slot: slot,
+                depth,
+            });
 
-            let mut emit_pulse = |value| {
-                let new_pulse_src = dst;
-                for new_pulse_dst in &outputs[new_pulse_src] {
-                    pulses.push_back((new_pulse_src, new_pulse_dst, value))
+            let out_level = match self.kinds[dst] {
+                ModuleKind::Broadcaster => level,
+                ModuleKind::FlipFlop => {
+                    if level == HIGH {
+                        continue;
+                    }
+                    self.state.on[dst] = !self.state.on[dst];
+                    self.state.on[dst]
+                }
+                ModuleKind::Conj => {
+                    let memory = &mut self.state.memory[dst];
+                    memory[slot] = level;
+                    !memory.iter().all(|&l| l == HIGH)
                 }
+                ModuleKind::Button | ModuleKind::Untyped => continue,
             };
+            for &(out, out_slot) in &self.outputs[dst] {
+                self.pending_pulses
+                    .push_back((dst, out, out_slot, out_level));
+            }
+        }
+        stats
+    }
+}
 
-            let Some(module) = modules.get_mut(dst) else {
-                continue;
-            };
-            match module {
-                Module::Broadcaster {} => {
-                    emit_pulse(value);
+impl PulseNetwork<'_> {
+    /// Counts the button presses it takes for the target module to get a low pulse.
+    ///
+    /// On the real input, that takes way too many presses to simulate. But the network is built in
+    /// a particular way: the target is fed by a single conjunction, which sends it a low pulse when
+    /// all of its inputs have sent it a high pulse. Each of these inputs, together with all the
+    /// modules upstream of it, forms a subcircuit that only gets pulses from the broadcaster, so
+    /// it evolves independently of the other subcircuits. We simulate all of them until each one's
+    /// state repeats, noting on which presses its input on the final conjunction is high (either
+    /// because it sends a high pulse, or because it was left high by the previous press), and then
+    /// find the first press on which all of them are, like with the ghosts on day 8.
+    ///
+    /// Being high on the same press is not enough though: they need to be high at the same time.
+    /// So we also note the depth of the pulses that set each input high and low again, and only
+    /// count presses on which the last input goes high before the first one goes low. If that
+    /// depends on the order of pulses from different subcircuits with the same depth, which can't
+    /// be known from the subcircuits alone, this returns an error. Same as when the network can't
+    /// be split into independent subcircuits.
+    fn count_presses_until_low_pulse(&mut self, target: &str) -> aoc::Result<u128> {
+        let target = self
+            .index(target)
+            .with_context(|| format!("module '{target}' not found"))?;
+        let final_conj = match self.inputs[target][..] {
+            [input] if self.kinds[input] == ModuleKind::Conj => input,
+            _ => bail!(
+                "network is not decomposable: '{}' isn't fed by a single conjunction",
+                self.names[target]
+            ),
+        };
+        ensure!(
+            !self.inputs[final_conj].is_empty(),
+            "network is not decomposable: '{}' has no inputs",
+            self.names[final_conj]
+        );
+        let subcircuits = self.find_subcircuits(final_conj)?;
+
+        let mut seen_states = subcircuits
+            .iter()
+            .enumerate()
+            .map(|(i, modules)| HashMap::from([(self.subcircuit_state(modules, final_conj, i), 0)]))
+            .collect_vec();
+        let mut cycles: Vec<Option<HighPresses>> = vec![None; subcircuits.len()];
+        let mut high_presses: Vec<Vec<(u64, Vec<HighWindow>)>> = vec![vec![]; subcircuits.len()];
+        for press in 1_u64.. {
+            // Inputs left high by the previous press count as high since the start of this one.
+            let mut rises = self.state.memory[final_conj]
+                .iter()
+                .map(|&level| (level == HIGH).then_some(0))
+                .collect_vec();
+            let mut windows = vec![vec![]; subcircuits.len()];
+            let mut target_got_low_pulse = false;
+            self.push_button_with(|pulse| {
+                target_got_low_pulse |= pulse.dst_index == target && pulse.level == LOW;
+                if pulse.dst_index != final_conj {
+                    return;
                 }
-                Module::FlipFlop { state } => {
-                    if value == LOW {
-                        *state = !*state;
-                        emit_pulse(*state);
+                let slot = pulse.dst_slot;
+                match (pulse.level, rises[slot]) {
+                    (HIGH, None) => rises[slot] = Some(pulse.depth),
+                    (LOW, Some(rise)) => {
+                        windows[slot].push((rise, pulse.depth));
+                        rises[slot] = None;
                     }
+                    _ => {}
                 }
-                Module::Conj { mem } => {
-                    mem.insert(src, value);
-                    let emit_val = !mem.values().all(|&v| v == HIGH);
-                    emit_pulse(emit_val);
-                    if emit_val == LOW && !conj_first_low_emits.contains_key(dst) {
-                        conj_first_low_emits.insert(dst, button_push);
-                    }
+            });
+            // Reached the target while simulating, so there's nothing to extrapolate.
+            if target_got_low_pulse {
+                return Ok(press as u128);
+            }
+            for (slot, rise) in rises.into_iter().enumerate() {
+                if let Some(rise) = rise {
+                    windows[slot].push((rise, usize::MAX));
+                }
+            }
+            for (i, windows) in windows.into_iter().enumerate() {
+                if !windows.is_empty() {
+                    high_presses[i].push((press, windows));
                 }
             }
-        }
 
-        if button_push == 1000 {
-            let part_1_ans = low_count * high_count;
-            if is_real_input {
-                print!("{part_1_ans} ");
-            } else {
-                println!("{part_1_ans}");
+            for (i, modules) in subcircuits.iter().enumerate() {
+                if cycles[i].is_some() {
+                    continue;
+                }
+                let state = self.subcircuit_state(modules, final_conj, i);
+                if let Some(&offset) = seen_states[i].get(&state) {
+                    cycles[i] = Some(group_high_presses(&high_presses[i], offset, press - offset));
+                } else {
+                    seen_states[i].insert(state, press);
+                }
+            }
+            if cycles.iter().all(Option::is_some) {
                 break;
             }
         }
 
-        // Note: multi-input conjunctions work like NANDs and single-input conjunctions, aka
-        // inverters, work like NOTs.
-        //
-        // This is *very* ad-hoc for our input module configuration. The "rx" module is fed by a
-        // single conjunction module that is in turn fed by a bunch of inverters that are fed by
-        // other conjunctions. So, the conjunction that feeds "rx" will emit a low pulse only when
-        // all these other conjunctions, in turn, emit a low pulse.
-        //
-        // We keep track of when these second-level conjunctions emit their first low pulse, and
-        // assume that this is their cycle time (because they are in turn fed by a bunch of
-        // flip-flops that repeat their cycles). So, we need to know when all these cycles coincide.
-        // Luckily, all these cycles are prime-numbers, so multiplying them together we get the
-        // super-cycle that makes them all emit low pulses at the same time, and in turn get the
-        // first low pulse into "rx".
-        //
-        // This solution definitely does *not* generalize to other module configurations.
-        if is_real_input {
-            let first_low_pulse_to_rx: Option<u64> = modules
-                .iter()
-                .filter(|&(name, module)| {
-                    matches!(module, Module::Conj { mem } if mem.len() > 1)
-                        && outputs[name] != ["rx"]
-                })
-                .map(|(name, _module)| conj_first_low_emits.get(name))
-                .product();
-            // first_low_pulse_to_rx is Some only when all second-level conjugations have emitted
-            // their first low pulse.
-            if let Some(part_2_ans) = first_low_pulse_to_rx {
-                println!("{part_2_ans:?}");
-                break;
+        // Try all combinations of how each subcircuit can be high during a press.
+        let mut first_press: Option<u128> = None;
+        let mut first_unclear_press: Option<u128> = None;
+        let cycles = cycles.into_iter().flatten().collect_vec();
+        for combination in cycles.iter().map(|c| c.iter()).multi_cartesian_product() {
+            let windows = combination.iter().map(|(w, _)| w.as_slice()).collect_vec();
+            let all_high = all_high_together(&windows);
+            if all_high == Some(false) {
+                continue;
             }
+            let periodics = combination.iter().map(|(_, p)| p.clone()).collect_vec();
+            let Some(presses_before) = congruence::first_common_hit(&periodics) else {
+                continue;
+            };
+            let press = presses_before + 1;
+            let first = if all_high.is_some() {
+                &mut first_press
+            } else {
+                &mut first_unclear_press
+            };
+            *first = Some(first.map_or(press, |first| first.min(press)));
         }
+        if let Some(unclear_press) = first_unclear_press {
+            ensure!(
+                first_press.is_some_and(|press| press < unclear_press),
+                "network is not decomposable: can't tell whether all inputs of '{}' are high at \
+                the same time on press {unclear_press}",
+                self.names[final_conj]
+            );
+        }
+        first_press.with_context(|| format!("'{}' never gets a low pulse", self.names[target]))
     }
 
-    Ok(())
+    /// Finds the modules upstream of each input of the given conjunction, in input slot order,
+    /// checking that no two of these subcircuits share any modules, besides the broadcaster.
+    fn find_subcircuits(&self, final_conj: usize) -> aoc::Result<Vec<Vec<usize>>> {
+        let mut owners: HashMap<usize, usize> = HashMap::new();
+        let mut subcircuits = vec![];
+        for &input in &self.inputs[final_conj] {
+            let mut modules = vec![input];
+            let mut pending = vec![input];
+            if let Some(&owner) = owners.get(&input) {
+                bail!(
+                    "network is not decomposable: '{}' feeds both '{}' and '{}'",
+                    self.names[input],
+                    self.names[owner],
+                    self.names[final_conj]
+                );
+            }
+            owners.insert(input, input);
+            while let Some(module) = pending.pop() {
+                for &upstream in &self.inputs[module] {
+                    match self.kinds[upstream] {
+                        ModuleKind::Button | ModuleKind::Broadcaster => continue,
+                        _ if upstream == final_conj => bail!(
+                            "network is not decomposable: '{}' feeds back into '{}'",
+                            self.names[input],
+                            self.names[final_conj]
+                        ),
+                        _ => {}
+                    }
+                    match owners.get(&upstream) {
+                        Some(&owner) if owner == input => {}
+                        Some(&owner) => bail!(
+                            "network is not decomposable: '{}' feeds both '{}' and '{}'",
+                            self.names[upstream],
+                            self.names[owner],
+                            self.names[input]
+                        ),
+                        None => {
+                            owners.insert(upstream, input);
+                            modules.push(upstream);
+                            pending.push(upstream);
+                        }
+                    }
+                }
+            }
+            subcircuits.push(modules);
+        }
+        Ok(subcircuits)
+    }
+
+    /// The state of the given subcircuit's modules, along with the level it left on its input
+    /// slot of the final conjunction, as that also affects on which presses it counts as high.
+    fn subcircuit_state(&self, modules: &[usize], final_conj: usize, slot: usize) -> Vec<bool> {
+        let mut state = vec![self.state.memory[final_conj][slot]];
+        for &module in modules {
+            state.push(self.state.on[module]);
+            state.extend(&self.state.memory[module]);
+        }
+        state
+    }
 }
 
-const LOW: bool = false;
-const HIGH: bool = true;
+/// When an input of the final conjunction is high during a press, as the depths of the pulses that
+/// set it high and low again. Inputs left high by the previous press go high at depth 0, and the
+/// ones that stay high at the end of the press go low at `usize::MAX`.
+type HighWindow = (usize, usize);
+
+/// The presses on which a subcircuit's input on the final conjunction is high, grouped by when it's
+/// high during those presses.
+type HighPresses = Vec<(Vec<HighWindow>, congruence::Periodic)>;
 
-enum Module<'a> {
-    Broadcaster {},
-    FlipFlop { state: bool },
-    Conj { mem: HashMap<&'a str, bool> },
+/// Groups the high presses of a subcircuit whose state after `offset` presses repeats every
+/// `length` presses. What repeats is the state after some number of presses, so the presses are
+/// counted by the number of presses before them.
+fn group_high_presses(
+    high_presses: &[(u64, Vec<HighWindow>)],
+    offset: u64,
+    length: u64,
+) -> HighPresses {
+    high_presses
+        .iter()
+        .map(|(press, windows)| (windows.clone(), press - 1))
+        .into_group_map()
+        .into_iter()
+        .sorted()
+        .map(|(windows, hits)| {
+            let periodic = congruence::Periodic {
+                offset,
+                length,
+                hits,
+            };
+            (windows, periodic)
+        })
+        .collect()
+}
+
+/// Whether all inputs of the final conjunction are high at the same time during a press, given
+/// when each of them is high. That's when the last one goes high before the first one goes low
+/// again. `None` if they happen at the same depth, as then it depends on their order.
+fn all_high_together(windows: &[&[HighWindow]]) -> Option<bool> {
+    let mut unclear = false;
+    for choice in windows.iter().map(|w| w.iter()).multi_cartesian_product() {
+        let last_rise = choice.iter().map(|&&(rise, _)| rise).max()?;
+        let first_fall = choice.iter().map(|&&(_, fall)| fall).min()?;
+        match last_rise.cmp(&first_fall) {
+            Ordering::Less => return Some(true),
+            Ordering::Equal => unclear = true,
+            Ordering::Greater => {}
+        }
+    }
+    if unclear {
+        None
+    } else {
+        Some(false)
+    }
+}
+
+impl fmt::Display for Pulse<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.level == HIGH { "high" } else { "low" };
+        write!(f, "{} -{level}-> {}", self.src, self.dst)
+    }
 }
 
-fn parse_modules(input: &str) -> aoc::Result<Vec<(&str, Module, Vec<&str>)>> {
-    input.lines().map(|line| parse_module(line)).try_collect()
+fn parse_number_var(name: &str) -> aoc::Result<Option<u64>> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    let n = value
+        .parse()
+        .with_context(|| format!("{name} must be a number"))?;
+    Ok(Some(n))
 }
 
-fn parse_module(line: &str) -> aoc::Result<(&str, Module, Vec<&str>)> {
+fn parse_module(line: &str) -> aoc::Result<(&str, ModuleKind, Vec<&str>)> {
     let (name, outputs) = line.split_once(" -> ").context("invalid line")?;
     let outputs = outputs.split(", ").collect();
     if name == "broadcaster" {
-        Ok((name, Module::Broadcaster {}, outputs))
+        Ok((name, ModuleKind::Broadcaster, outputs))
     } else if let Some(name) = name.strip_prefix('%') {
-        Ok((name, Module::FlipFlop { state: false }, outputs))
+        Ok((name, ModuleKind::FlipFlop, outputs))
     } else if let Some(name) = name.strip_prefix('&') {
-        let module = Module::Conj {
-            mem: HashMap::new(),
-        };
-        Ok((name, module, outputs))
+        Ok((name, ModuleKind::Conj, outputs))
     } else {
         bail!("invalid module name {name}")
     }
@@ -1,14 +1,27 @@
+use anyhow::Context;
 use itertools::Itertools;
+use std::{env, ops::RangeInclusive};
 
+/// Note: Run this daily solution with TEST_AREA=<min>,<max> env var to use a different test area
+/// than the real input's one. The sample uses 7,27.
 fn main() -> aoc::Result<()> {
     let input = aoc::read_stdin()?;
     let hailstones: Vec<_> = input.lines().map(parse_hailstone).try_collect()?;
+    let test_area = match env::var("TEST_AREA") {
+        Ok(value) => {
+            let (min, max) = value
+                .split_once(',')
+                .context("TEST_AREA must be <min>,<max>")?;
+            let bound = |s: &str| s.trim().parse().context("TEST_AREA bounds must be numbers");
+            bound(min)?..=bound(max)?
+        }
+        Err(_) => 200000000000000.0..=400000000000000.0,
+    };
 
-    let is_sample = hailstones.len() < 10;
     let intersections_count = hailstones
         .iter()
         .tuple_combinations()
-        .filter(|(&h1, &h2)| intersect_in_test_area(h1, h2, is_sample))
+        .filter(|(&h1, &h2)| intersect_in_test_area(h1, h2, &test_area))
         .count();
     println!("{intersections_count}");
     Ok(())
@@ -17,7 +30,7 @@ fn main() -> aoc::Result<()> {
 type Point = (f64, f64, f64);
 type Hailstone = (Point, Point);
 
-fn intersect_in_test_area(h1: Hailstone, h2: Hailstone, is_sample: bool) -> bool {
+fn intersect_in_test_area(h1: Hailstone, h2: Hailstone, test_area: &RangeInclusive<f64>) -> bool {
     // The equations for the 2D lines of both hailstones are:
     // h1: (x, y) = (x1, y1) + t*(vx1, vy1)
     // h2: (x, y) = (x2, y2) + v*(vx2, vy2)
@@ -52,11 +65,6 @@ fn intersect_in_test_area(h1: Hailstone, h2: Hailstone, is_sample: bool) -> bool
 
     let (x, y) = (x1 + t * vx1, y1 + t * vy1);
 
-    let test_area = if is_sample {
-        7.0..=27.0
-    } else {
-        200000000000000.0..=400000000000000.0
-    };
     test_area.contains(&x) && test_area.contains(&y)
 }
 
@@ -0,0 +1,208 @@
+//! Modular arithmetic for finding when periodic events coincide.
+
+use anyhow::Context;
+use itertools::Itertools;
+
+/// The times at which something that ends up repeating happens, like a ghost being on an end node.
+/// From time `offset` onwards, everything repeats every `length` time units, so `hits` only needs
+/// to list the times before `offset + length`.
+#[derive(Debug, Clone)]
+pub struct Periodic {
+    pub offset: u64,
+    pub length: u64,
+    pub hits: Vec<u64>,
+}
+
+impl Periodic {
+    pub fn is_hit(&self, time: u64) -> bool {
+        let time = if time < self.offset {
+            time
+        } else {
+            self.offset + (time - self.offset) % self.length
+        };
+        self.hits.contains(&time)
+    }
+}
+
+/// Finds the first time at which all the given periodic things happen at once, or None if they
+/// never do.
+pub fn first_common_hit(periodics: &[Periodic]) -> Option<u128> {
+    // Any time before all of them have entered their cycles must be one of the hits of the one that
+    // takes longest to enter its cycle, so these can be checked directly.
+    let max_offset = periodics.iter().map(|p| p.offset).max().unwrap_or(0);
+    let early_hit = periodics
+        .iter()
+        .flat_map(|p| p.hits.iter().copied())
+        .filter(|&time| time < max_offset)
+        .sorted()
+        .find(|&time| periodics.iter().all(|p| p.is_hit(time)));
+    if let Some(time) = early_hit {
+        return Some(time as u128);
+    }
+
+    // Otherwise, each one happens at a time `t` after its offset iff `t ≡ hit (mod length)` for any
+    // of the hits on its cycle. Combine all these congruences.
+    let mut congruences = vec![(0, 1)];
+    for periodic in periodics {
+        let cycle_congruences = periodic
+            .hits
+            .iter()
+            .filter(|&&time| time >= periodic.offset)
+            .map(|&time| (time as u128, periodic.length as u128))
+            .collect_vec();
+        congruences = congruences
+            .into_iter()
+            .cartesian_product(cycle_congruences)
+            .filter_map(|(a, b)| crt(a, b))
+            .unique()
+            .collect();
+    }
+    // Each combined congruence gives the first time after all offsets as the smallest t >=
+    // max_offset with t ≡ r (mod m).
+    let min_time = max_offset as u128;
+    congruences
+        .into_iter()
+        .filter_map(|(r, m)| {
+            min_time
+                .saturating_sub(r)
+                .div_ceil(m)
+                .checked_mul(m)?
+                .checked_add(r)
+        })
+        .min()
+}
+
+/// Combines the congruences `t ≡ a1 (mod m1)` and `t ≡ a2 (mod m2)` into a single `t ≡ a (mod
+/// lcm(m1, m2))` using the generalized Chinese remainder theorem, which doesn't require the moduli
+/// to be coprime. Returns None if there is no `t` that satisfies both congruences.
+///
+/// See https://en.wikipedia.org/wiki/Chinese_remainder_theorem#Generalization_to_non-coprime_moduli
+pub fn crt((a1, m1): (u128, u128), (a2, m2): (u128, u128)) -> Option<(u128, u128)> {
+    let g = gcd(m1, m2);
+    let diff = (a2 % m2 + m2 - a1 % m2) % m2;
+    if diff % g != 0 {
+        return None;
+    }
+    // Solve k * m1 ≡ diff (mod m2) for k, reduced to k * (m1/g) ≡ diff/g (mod m2/g).
+    let m2_g = m2 / g;
+    let k = (diff / g) % m2_g * mod_inverse(m1 / g % m2_g, m2_g)? % m2_g;
+    let m = m1.checked_mul(m2_g)?;
+    Some(((a1 + k * m1) % m, m))
+}
+
+fn mod_inverse(a: u128, m: u128) -> Option<u128> {
+    if m == 1 {
+        return Some(0);
+    }
+    // Extended Euclidean algorithm.
+    let (mut old_r, mut r) = (a as i128, m as i128);
+    let (mut old_s, mut s) = (1_i128, 0_i128);
+    while r != 0 {
+        let q = old_r / r;
+        (old_r, r) = (r, old_r - q * r);
+        (old_s, s) = (s, old_s - q * s);
+    }
+    if old_r != 1 {
+        return None;
+    }
+    Some(old_s.rem_euclid(m as i128) as u128)
+}
+
+pub fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        return a;
+    }
+    gcd(b, a % b)
+}
+
+/// The least common multiple of `a` and `b`, or an error if it doesn't fit in a u128.
+pub fn lcm(a: u128, b: u128) -> crate::Result<u128> {
+    if a == 0 || b == 0 {
+        return Ok(0);
+    }
+    (a / gcd(a, b))
+        .checked_mul(b)
+        .with_context(|| format!("least common multiple of {a} and {b} overflows"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crt_coprime_moduli() {
+        assert_eq!(crt((2, 3), (3, 5)), Some((8, 15)));
+    }
+
+    #[test]
+    fn crt_non_coprime_moduli() {
+        // 10 is the only number below lcm(4, 6) = 12 that is 2 mod 4 and 4 mod 6.
+        assert_eq!(crt((2, 4), (4, 6)), Some((10, 12)));
+        assert_eq!(crt((3, 6), (3, 9)), Some((3, 18)));
+    }
+
+    #[test]
+    fn crt_no_solution() {
+        // Odd numbers mod 4 are never even mod 6.
+        assert_eq!(crt((1, 4), (2, 6)), None);
+        assert_eq!(crt((1, 10), (2, 15)), None);
+    }
+
+    #[test]
+    fn first_common_hit_in_cycles() {
+        // Hits at 2, 5, 8, 11, ... and 0, 3, 7, 11, ... so 11 is the first common one.
+        let a = Periodic {
+            offset: 0,
+            length: 3,
+            hits: vec![2],
+        };
+        let b = Periodic {
+            offset: 1,
+            length: 4,
+            hits: vec![0, 3],
+        };
+        assert_eq!(first_common_hit(&[a.clone(), b.clone()]), Some(11));
+        assert_eq!(first_common_hit(&[a]), Some(2));
+        assert_eq!(first_common_hit(&[b]), Some(0));
+    }
+
+    #[test]
+    fn first_common_hit_before_cycles() {
+        // Hits at 3 before entering a cycle of even times.
+        let a = Periodic {
+            offset: 4,
+            length: 2,
+            hits: vec![3, 4],
+        };
+        let b = Periodic {
+            offset: 0,
+            length: 3,
+            hits: vec![0],
+        };
+        assert_eq!(first_common_hit(&[a, b]), Some(3));
+    }
+
+    #[test]
+    fn first_common_hit_never() {
+        let even = Periodic {
+            offset: 0,
+            length: 2,
+            hits: vec![0],
+        };
+        let odd = Periodic {
+            offset: 0,
+            length: 2,
+            hits: vec![1],
+        };
+        assert_eq!(first_common_hit(&[even, odd]), None);
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(gcd(12, 18), 6);
+        assert_eq!(lcm(12, 18).unwrap(), 36);
+        assert_eq!(lcm(0, 5).unwrap(), 0);
+        assert_eq!(lcm(u128::MAX, 1).unwrap(), u128::MAX);
+        assert!(lcm(u128::MAX, 2).is_err());
+    }
+}
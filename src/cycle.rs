@@ -0,0 +1,150 @@
+//! Cycle detection for sequences generated by repeatedly applying a step function to some initial
+//! state: `x0, f(x0), f(f(x0)), ...`.
+//!
+//! The cycle detection functions return the cycle as a `(prefix length, cycle length)` pair, where
+//! the prefix length is the index of the first state that belongs to the cycle, a.k.a. μ, and the
+//! cycle length is the number of steps it takes to get back to that same state, a.k.a. λ.
+//!
+//! Note that these functions never terminate for sequences that never repeat.
+//!
+//! See https://en.wikipedia.org/wiki/Cycle_detection
+
+use std::{collections::HashMap, hash::Hash};
+
+/// Finds the cycle using Brent's algorithm, which only needs to keep two states at a time. Compared
+/// to Floyd's "tortoise and hare" algorithm, it calls the step function fewer times.
+pub fn brent<T: Clone + PartialEq>(start: T, mut step: impl FnMut(&T) -> T) -> (usize, usize) {
+    // Find the cycle length by moving the tortoise to the hare's position on every power of two.
+    let mut power = 1;
+    let mut cycle_len = 1;
+    let mut tortoise = start.clone();
+    let mut hare = step(&start);
+    while tortoise != hare {
+        if power == cycle_len {
+            tortoise = hare.clone();
+            power *= 2;
+            cycle_len = 0;
+        }
+        hare = step(&hare);
+        cycle_len += 1;
+    }
+
+    // Put the hare one cycle length ahead of the tortoise, and move them both until they meet at
+    // the start of the cycle.
+    let mut prefix_len = 0;
+    tortoise = start.clone();
+    hare = start;
+    for _ in 0..cycle_len {
+        hare = step(&hare);
+    }
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        hare = step(&hare);
+        prefix_len += 1;
+    }
+
+    (prefix_len, cycle_len)
+}
+
+/// Finds the cycle, and the state after `n` steps, which can be a huge number. Uses [`brent`] to
+/// find the cycle, so it only keeps a few states at a time, and then steps from the start to the
+/// state in the cycle that matches the n-th one.
+pub fn state_after<T: Clone + PartialEq>(
+    start: T,
+    n: u64,
+    mut step: impl FnMut(&T) -> T,
+) -> (usize, usize, T) {
+    let (prefix_len, cycle_len) = brent(start.clone(), &mut step);
+    let index = cycle_index(prefix_len, cycle_len, n);
+    let mut state = start;
+    for _ in 0..index {
+        state = step(&state);
+    }
+    (prefix_len, cycle_len, state)
+}
+
+/// Like [`state_after`], but remembers the key of every state, given by the key function, to find
+/// the cycle in a single pass. Only the states up to the n-th one are kept.
+pub fn state_after_by_key<T: Clone, K: Hash + Eq>(
+    start: T,
+    n: u64,
+    mut step: impl FnMut(&T) -> T,
+    key: impl Fn(&T) -> K,
+) -> (usize, usize, T) {
+    let mut seen = HashMap::from([(key(&start), 0)]);
+    let mut states = vec![start.clone()];
+    let mut state = start;
+    let mut index = 0;
+    loop {
+        state = step(&state);
+        index += 1;
+        let state_key = key(&state);
+        if let Some(&prefix_len) = seen.get(&state_key) {
+            let cycle_len = index - prefix_len;
+            let n_index = cycle_index(prefix_len, cycle_len, n);
+            return (prefix_len, cycle_len, states.swap_remove(n_index));
+        }
+        seen.insert(state_key, index);
+        if index as u64 <= n {
+            states.push(state.clone());
+        }
+    }
+}
+
+/// The index of the first state that's the same as the n-th one.
+fn cycle_index(prefix_len: usize, cycle_len: usize, n: u64) -> usize {
+    if n < prefix_len as u64 {
+        n as usize
+    } else {
+        prefix_len + ((n - prefix_len as u64) % cycle_len as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 0, 1, 2, 5, 26, 167, 95, 101, 2, 5, ... so the prefix is 2 long, and the cycle 6.
+    fn step(x: &u64) -> u64 {
+        (x * x + 1) % 255
+    }
+
+    #[test]
+    fn brent_finds_cycle() {
+        assert_eq!(brent(0, step), (2, 6));
+    }
+
+    #[test]
+    fn state_after_skips_cycles() {
+        assert_eq!(state_after(0, 0, step), (2, 6, 0));
+        assert_eq!(state_after(0, 1, step), (2, 6, 1));
+        assert_eq!(state_after(0, 7, step), (2, 6, 101));
+        assert_eq!(state_after(0, 8, step), (2, 6, 2));
+        assert_eq!(state_after(0, 1000, step), (2, 6, 26));
+    }
+
+    #[test]
+    fn state_after_by_key_skips_cycles() {
+        let key = |x: &u64| x % 255;
+        assert_eq!(state_after_by_key(0, 0, step, key), (2, 6, 0));
+        assert_eq!(state_after_by_key(0, 1, step, key), (2, 6, 1));
+        assert_eq!(state_after_by_key(0, 7, step, key), (2, 6, 101));
+        assert_eq!(state_after_by_key(0, 8, step, key), (2, 6, 2));
+        assert_eq!(state_after_by_key(0, 1000, step, key), (2, 6, 26));
+    }
+
+    #[test]
+    fn state_after_by_key_steps_once_per_state() {
+        let mut steps = 0;
+        let result = state_after_by_key(
+            0,
+            1000,
+            |x| {
+                steps += 1;
+                step(x)
+            },
+            u64::clone,
+        );
+        assert_eq!((result, steps), ((2, 6, 26), 8));
+    }
+}
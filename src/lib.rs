@@ -1,6 +1,11 @@
 use itertools::Itertools;
 use std::{io, result, str::FromStr};
 
+pub mod congruence;
+pub mod cycle;
+pub mod polygon;
+pub mod shortest_path;
+
 pub type Result<T> = anyhow::Result<T>;
 
 pub fn read_stdin() -> result::Result<String, io::Error> {
@@ -0,0 +1,202 @@
+//! Polygons on the integer lattice, given by their vertices in order. Polygons are implicitly
+//! closed: there's always an edge from the last vertex back to the first one. Edges are numbered
+//! from 1, with edge #k going from vertex k-1 to vertex k.
+
+use anyhow::{bail, ensure};
+use itertools::Itertools;
+
+pub type Point = (i64, i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Checks that a path ends where it started, and returns it as the polygon vertices, without the
+/// repeated end point.
+pub fn close_path(path: &[Point]) -> crate::Result<&[Point]> {
+    let (Some(first), Some(last)) = (path.first(), path.last()) else {
+        bail!("empty path")
+    };
+    ensure!(
+        first == last,
+        "path doesn't close: starts at {first:?} but ends at {last:?}"
+    );
+    Ok(&path[..path.len() - 1])
+}
+
+/// Checks that the polygon is simple, i.e. that its edges only touch their neighbors, and only at
+/// the vertex they share.
+///
+/// Compares every pair of edges, so it takes O(n²) time. Fine for a few thousand vertices.
+pub fn check_simple(vertices: &[Point]) -> crate::Result<()> {
+    let n = vertices.len();
+    ensure!(n >= 3, "a polygon needs at least 3 vertices, got {n}");
+    let edges = || (0..n).map(|i| (vertices[i], vertices[(i + 1) % n]));
+    for (i, (a, b)) in edges().enumerate() {
+        ensure!(a != b, "edge #{} has zero length", i + 1);
+    }
+    for ((i, (a, b)), (j, (c, d))) in edges().enumerate().tuple_combinations() {
+        if j == i + 1 || (i == 0 && j == n - 1) {
+            // Neighbor edges share a vertex; they only overlap if the second one doubles back over
+            // the first one. For the wrap-around pair the shared vertex is the other way around.
+            let (prev, shared, next) = if j == i + 1 { (a, b, d) } else { (c, a, b) };
+            let collinear = orientation(prev, shared, next) == 0;
+            let doubles_back = dot(sub(shared, prev), sub(next, shared)) < 0;
+            if collinear && doubles_back {
+                bail!("edges #{} and #{} overlap", i + 1, j + 1);
+            }
+        } else if segments_intersect((a, b), (c, d)) {
+            bail!(
+                "edges #{} ({a:?} to {b:?}) and #{} ({c:?} to {d:?}) cross each other",
+                i + 1,
+                j + 1
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Twice the signed area of the polygon, using the Shoelace formula. Positive when vertices go
+/// counter-clockwise with the y axis pointing up, or clockwise with the y axis pointing down.
+///
+/// See https://en.wikipedia.org/wiki/Shoelace_formula
+pub fn twice_signed_area(vertices: &[Point]) -> i128 {
+    vertices
+        .iter()
+        .circular_tuple_windows()
+        .map(|(&(x1, y1), &(x2, y2))| x1 as i128 * y2 as i128 - x2 as i128 * y1 as i128)
+        .sum()
+}
+
+/// Twice the area of the polygon. Doubled so that it's always an integer.
+pub fn twice_area(vertices: &[Point]) -> u128 {
+    twice_signed_area(vertices).unsigned_abs()
+}
+
+/// The winding order of the polygon, assuming the y axis points up. `None` if it has no area.
+pub fn winding(vertices: &[Point]) -> Option<Winding> {
+    match twice_signed_area(vertices).signum() {
+        1 => Some(Winding::CounterClockwise),
+        -1 => Some(Winding::Clockwise),
+        _ => None,
+    }
+}
+
+/// Counts the lattice points on the boundary of the polygon. An edge going (dx, dy) goes through
+/// gcd(|dx|, |dy|) lattice points, not counting its start.
+pub fn boundary_points(vertices: &[Point]) -> u128 {
+    vertices
+        .iter()
+        .circular_tuple_windows()
+        .map(|(&(x1, y1), &(x2, y2))| gcd(x1.abs_diff(x2), y1.abs_diff(y2)) as u128)
+        .sum()
+}
+
+/// Counts the lattice points strictly inside a simple polygon, using Pick's theorem:
+/// `A = I + B/2 - 1`, where A is the area, I the interior points, and B the boundary points.
+///
+/// See https://en.wikipedia.org/wiki/Pick%27s_theorem
+pub fn interior_points(vertices: &[Point]) -> u128 {
+    // Saturating, as this doesn't hold for non-simple polygons, which could have less area than
+    // boundary points.
+    (twice_area(vertices) + 2).saturating_sub(boundary_points(vertices)) / 2
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn sub((x1, y1): Point, (x2, y2): Point) -> (i128, i128) {
+    (x1 as i128 - x2 as i128, y1 as i128 - y2 as i128)
+}
+
+fn dot((x1, y1): (i128, i128), (x2, y2): (i128, i128)) -> i128 {
+    x1 * x2 + y1 * y2
+}
+
+/// The sign of the turn from a to b to c: 1 for counter-clockwise, -1 for clockwise, 0 if they're
+/// collinear.
+fn orientation(a: Point, b: Point, c: Point) -> i128 {
+    let (ux, uy) = sub(b, a);
+    let (vx, vy) = sub(c, a);
+    (ux * vy - uy * vx).signum()
+}
+
+/// Whether point p, collinear with segment ab, lies on it.
+fn on_segment((a, b): (Point, Point), p: Point) -> bool {
+    a.0.min(b.0) <= p.0 && p.0 <= a.0.max(b.0) && a.1.min(b.1) <= p.1 && p.1 <= a.1.max(b.1)
+}
+
+/// Whether two closed segments have any point in common.
+fn segments_intersect((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let o1 = orientation(a, b, c);
+    let o2 = orientation(a, b, d);
+    let o3 = orientation(c, d, a);
+    let o4 = orientation(c, d, b);
+    if o1 * o2 < 0 && o3 * o4 < 0 {
+        return true;
+    }
+    (o1 == 0 && on_segment((a, b), c))
+        || (o2 == 0 && on_segment((a, b), d))
+        || (o3 == 0 && on_segment((c, d), a))
+        || (o4 == 0 && on_segment((c, d), b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 square, going counter-clockwise with the y axis pointing up.
+    const SQUARE: [Point; 4] = [(0, 0), (2, 0), (2, 2), (0, 2)];
+
+    #[test]
+    fn close_path_drops_end_point() {
+        let path = [(0, 0), (2, 0), (2, 2), (0, 2), (0, 0)];
+        assert_eq!(close_path(&path).unwrap(), &SQUARE);
+    }
+
+    #[test]
+    fn close_path_errors() {
+        assert!(close_path(&[]).is_err());
+        assert!(close_path(&SQUARE).is_err());
+    }
+
+    #[test]
+    fn check_simple_accepts_simple_polygon() {
+        assert!(check_simple(&SQUARE).is_ok());
+    }
+
+    #[test]
+    fn check_simple_rejects_non_simple_polygons() {
+        // Too few vertices.
+        assert!(check_simple(&[(0, 0), (1, 1)]).is_err());
+        // Zero length edge.
+        assert!(check_simple(&[(0, 0), (2, 0), (2, 0), (0, 2)]).is_err());
+        // Second edge doubles back over the first one.
+        assert!(check_simple(&[(0, 0), (2, 0), (1, 0)]).is_err());
+        // Bowtie, with the first and third edges crossing.
+        assert!(check_simple(&[(0, 0), (2, 2), (2, 0), (0, 2)]).is_err());
+    }
+
+    #[test]
+    fn winding_order() {
+        let mut reversed = SQUARE;
+        reversed.reverse();
+        assert_eq!(winding(&SQUARE), Some(Winding::CounterClockwise));
+        assert_eq!(winding(&reversed), Some(Winding::Clockwise));
+        assert_eq!(winding(&[(0, 0), (1, 1), (2, 2)]), None);
+    }
+
+    #[test]
+    fn lattice_points() {
+        assert_eq!(twice_area(&SQUARE), 8);
+        assert_eq!(boundary_points(&SQUARE), 8);
+        assert_eq!(interior_points(&SQUARE), 1);
+    }
+}
@@ -0,0 +1,121 @@
+//! Shortest paths on graphs whose nodes are numbered densely, as `0..nodes_count`, so all the
+//! search state can be kept on plain arrays instead of hash maps.
+
+use anyhow::ensure;
+
+/// Finds the cheapest path from any of the `starts` nodes to a node that satisfies `success`, using
+/// Dial's algorithm: Dijkstra's algorithm with a circular array of buckets, one for each possible
+/// distance from the current one, instead of a binary heap. This makes each queue operation O(1),
+/// but only works for small integer edge costs, no bigger than `max_edge_cost`.
+///
+/// Returns the nodes of the path, including the start and end ones, and its total cost. Or `None`
+/// if no successful node can be reached. Fails if `successors` gives an edge that costs more than
+/// `max_edge_cost`.
+///
+/// See https://en.wikipedia.org/wiki/Dijkstra%27s_algorithm#Specialized_variants
+pub fn dial<IN>(
+    nodes_count: usize,
+    max_edge_cost: u32,
+    starts: impl IntoIterator<Item = usize>,
+    mut successors: impl FnMut(usize) -> IN,
+    mut success: impl FnMut(usize) -> bool,
+) -> crate::Result<Option<(Vec<usize>, u64)>>
+where
+    IN: IntoIterator<Item = (usize, u32)>,
+{
+    const UNREACHED: u64 = u64::MAX;
+    let mut costs = vec![UNREACHED; nodes_count];
+    let mut parents = vec![usize::MAX; nodes_count];
+    let mut buckets = vec![vec![]; max_edge_cost as usize + 1];
+    let buckets_count = buckets.len() as u64;
+    let bucket_index = |cost: u64| (cost % buckets_count) as usize;
+    let mut queued = 0;
+    for start in starts {
+        costs[start] = 0;
+        parents[start] = start;
+        buckets[0].push(start);
+        queued += 1;
+    }
+
+    let mut cost = 0;
+    while queued > 0 {
+        while let Some(node) = buckets[bucket_index(cost)].pop() {
+            queued -= 1;
+            // Nodes get queued again each time a cheaper path to them is found, so the previous
+            // entries get skipped.
+            if costs[node] != cost {
+                continue;
+            }
+            if success(node) {
+                let mut path = vec![node];
+                let mut current = node;
+                while parents[current] != current {
+                    current = parents[current];
+                    path.push(current);
+                }
+                path.reverse();
+                return Ok(Some((path, cost)));
+            }
+            for (next, edge_cost) in successors(node) {
+                ensure!(
+                    edge_cost <= max_edge_cost,
+                    "edge cost {edge_cost} is bigger than the max edge cost {max_edge_cost}"
+                );
+                let next_cost = cost + edge_cost as u64;
+                if next_cost < costs[next] {
+                    costs[next] = next_cost;
+                    parents[next] = node;
+                    buckets[bucket_index(next_cost)].push(next);
+                    queued += 1;
+                }
+            }
+        }
+        cost += 1;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 0 -5-> 1 -1-> 3, and 0 -1-> 2 -2-> 3, so the cheapest way to 3 goes through 2. Node 4 is
+    /// unreachable.
+    fn successors(node: usize) -> Vec<(usize, u32)> {
+        match node {
+            0 => vec![(1, 5), (2, 1)],
+            1 => vec![(3, 1)],
+            2 => vec![(3, 2)],
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn finds_cheapest_path() {
+        let result = dial(5, 5, [0], successors, |node| node == 3).unwrap();
+        assert_eq!(result, Some((vec![0, 2, 3], 3)));
+    }
+
+    #[test]
+    fn starts_from_any_start() {
+        let result = dial(5, 5, [0, 1], successors, |node| node == 3).unwrap();
+        assert_eq!(result, Some((vec![1, 3], 1)));
+    }
+
+    #[test]
+    fn start_can_be_the_goal() {
+        let result = dial(5, 5, [3], successors, |node| node == 3).unwrap();
+        assert_eq!(result, Some((vec![3], 0)));
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let result = dial(5, 5, [0], successors, |node| node == 4).unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn edge_cost_too_big() {
+        assert!(dial(5, 4, [0], successors, |node| node == 3).is_err());
+    }
+}